@@ -6,4 +6,4 @@ near deploy \
   --wasmFile $WASM_PATH \
   --accountId "$1" \
   --initFunction new \
-  --initArgs "$(node ./init-args.js)"
+  --initArgs "$(node ./init-args.js "$1")"
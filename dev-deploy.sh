@@ -6,5 +6,5 @@ near dev-deploy \
   --wasmFile $WASM_PATH \
   "$@"
 
-near call "$(<./neardev/dev-account)" new "$(node ./init-args.js)" \
+near call "$(<./neardev/dev-account)" new "$(node ./init-args.js "$(<./neardev/dev-account)")" \
   --accountId "$(<./neardev/dev-account)"
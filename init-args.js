@@ -3,4 +3,25 @@ const ONE_DAY = 1_000_000_000 * 60 * 60 * 24;
 // 1e24, calculated like this because JS numbers don't work that large
 const ONE_NEAR = BigInt(1e12) ** 2n;
 
-console.log(JSON.stringify({}));
+// node ./init-args.js <owner> [guardian,guardian...]
+const owner = process.argv[2];
+const guardians = process.argv[3] ? process.argv[3].split(",") : [owner];
+
+console.log(
+  JSON.stringify({
+    owner,
+    guardians,
+    tokens: [],
+    config: {
+      fee_bps: 0,
+      grant_quorum: 1,
+      upgrade_quorum: 1,
+      // Donors' votes on milestone evidence, at least a day long
+      milestone_quorum_bps: 2000,
+      milestone_threshold_bps: 5000,
+      milestone_vote_period: 7 * ONE_DAY,
+      // "equal" for one vote per donor, "weighted" for one per whole token donated
+      partner_voting: "equal",
+    },
+  })
+);
@@ -0,0 +1,14 @@
+#!/usr/bin/env bash
+
+WASM_PATH="$(find ./target/wasm32-unknown-unknown/release/ -maxdepth 1 -name "*.wasm")"
+
+near deploy \
+  --wasmFile $WASM_PATH \
+  --accountId "$1" \
+  --initFunction migrate \
+  --initArgs "{}"
+
+# Donations of the unversioned contract are moved in batches, until none are left
+while [ "$(near call "$1" migrate_donations '{"limit": 100}' --accountId "$1" --gas 300000000000000 | tail -1)" != "0" ]; do
+  :
+done
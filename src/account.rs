@@ -1,17 +1,162 @@
 
 
+use near_contract_standards::storage_management::{
+    StorageBalance, StorageBalanceBounds, StorageManagement,
+};
+use near_sdk::{Balance, Promise, StorageUsage};
+
+use crate::constants::INIT_ACCOUNT_STORAGE;
+use crate::errors::{ensure, ContractError, OrPanic};
 use crate::*;
 
+// Storage deposit of an account, paying for the records it creates (NEP-145)
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
 pub struct Account{
     pub id: AccountId,
+    pub deposit: Balance,             // NEAR deposited for storage
+    pub storage_used: StorageUsage,   // Bytes taken by campaigns, events, partners and donations created
 }
 
 
 impl Account{
+    pub fn new(id: AccountId, deposit: Balance) -> Self {
+        Self { id, deposit, storage_used: 0 }
+    }
+
+    // The account record itself plus everything it created
+    pub fn locked(&self) -> Balance {
+        Balance::from(INIT_ACCOUNT_STORAGE + self.storage_used) * env::storage_byte_cost()
+    }
 
+    pub fn available(&self) -> Balance {
+        self.deposit.saturating_sub(self.locked())
+    }
+
+    pub fn storage_balance(&self) -> StorageBalance {
+        StorageBalance {
+            total: U128(self.deposit),
+            available: U128(self.available()),
+        }
+    }
 }
 
 #[near_bindgen]
+impl StorageManagement for Contract{
+    #[payable]
+    fn storage_deposit(
+        &mut self,
+        account_id: Option<AccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
+        let amount = env::attached_deposit();
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        let min_balance = self.storage_balance_bounds().min.0;
+
+        let account = match self.accounts.get(&account_id) {
+            Some(mut account) => {
+                if registration_only.unwrap_or(false) {
+                    // Already registered, nothing to keep
+                    if amount > 0 {
+                        Promise::new(env::predecessor_account_id()).transfer(amount);
+                    }
+                } else {
+                    account.deposit += amount;
+                }
+                account
+            }
+            None => {
+                ensure(amount >= min_balance, ContractError::DepositLessThanMinStorage);
+                if registration_only.unwrap_or(false) {
+                    let refund = amount - min_balance;
+                    if refund > 0 {
+                        Promise::new(env::predecessor_account_id()).transfer(refund);
+                    }
+                    Account::new(account_id.clone(), min_balance)
+                } else {
+                    Account::new(account_id.clone(), amount)
+                }
+            }
+        };
+        self.accounts.insert(&account_id, &account);
+        account.storage_balance()
+    }
+
+    #[payable]
+    fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
+        ensure(env::attached_deposit() == 1, ContractError::OneYocto);
+        let account_id = env::predecessor_account_id();
+        let mut account = self.find_account(&account_id).or_panic();
+        let available = account.available();
+        let amount = amount.map_or(available, |amount| amount.0);
+        ensure(available > 0, ContractError::NoStorageCanWithdraw);
+        ensure(amount <= available, ContractError::StorageWithdrawTooMuch);
+
+        account.deposit -= amount;
+        self.accounts.insert(&account_id, &account);
+        Promise::new(account_id).transfer(amount);
+        account.storage_balance()
+    }
+
+    // Records created by the account stay, so unregistering with records needs force and forfeits their storage
+    #[payable]
+    fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        ensure(env::attached_deposit() == 1, ContractError::OneYocto);
+        let account_id = env::predecessor_account_id();
+        match self.accounts.get(&account_id) {
+            Some(account) => {
+                ensure(
+                    account.storage_used == 0 || force.unwrap_or(false),
+                    ContractError::StorageInUse,
+                );
+                self.accounts.remove(&account_id);
+                let refund = if account.storage_used == 0 {
+                    account.deposit
+                } else {
+                    account.available()
+                };
+                if refund > 0 {
+                    Promise::new(account_id).transfer(refund);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        StorageBalanceBounds {
+            min: U128(Balance::from(INIT_ACCOUNT_STORAGE) * env::storage_byte_cost()),
+            max: None,
+        }
+    }
+
+    fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance> {
+        self.accounts.get(&account_id).map(|account| account.storage_balance())
+    }
+}
+
 impl Contract{
-    
-}
\ No newline at end of file
+    pub fn find_account(&self, account_id: &AccountId) -> Result<Account, ContractError> {
+        self.accounts.get(account_id).ok_or(ContractError::AccNotRegistered)
+    }
+
+    // Checked before writing records that must not fail on storage, ie ones made for someone else's transaction
+    pub fn can_store(&self, account_id: &AccountId, bytes: StorageUsage) -> bool {
+        self.accounts.get(account_id).is_some_and(|account| {
+            account.available() >= Balance::from(bytes) * env::storage_byte_cost()
+        })
+    }
+
+    // Charges the account for the bytes written since initial_storage, freed bytes are credited back
+    pub fn charge_storage(&mut self, account_id: &AccountId, initial_storage: StorageUsage) {
+        let mut account = self.find_account(account_id).or_panic();
+        let current_storage = env::storage_usage();
+        if current_storage >= initial_storage {
+            account.storage_used += current_storage - initial_storage;
+        } else {
+            account.storage_used = account.storage_used.saturating_sub(initial_storage - current_storage);
+        }
+        ensure(account.deposit >= account.locked(), ContractError::InsufficientStorage);
+        self.accounts.insert(account_id, &account);
+    }
+}
@@ -1,7 +1,23 @@
 use std::collections::HashMap;
 
+use crate::constants::anonymous;
+use crate::errors::{ensure, ContractError, OrPanic};
+use crate::logs::{
+  log_campaign_created, log_campaign_finalised, log_campaign_updated, log_vote_cast,
+  CampaignFinalisedLog, CampaignUpdatedLog, RecordCreatedLog, VoteCastLog,
+};
+use crate::utils::timestamp_to_date;
 use crate::*;
 
+// How the votes of campaign donors for a partner are counted
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "lowercase")]
+pub enum PartnerVoting {
+  Equal,    // One vote per donor
+  Weighted, // One vote per whole token donated, at least one
+}
+
 #[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug)]
 #[serde(crate = "near_sdk::serde")]
 pub struct Campaign {
@@ -9,7 +25,7 @@ pub struct Campaign {
   pub created_by: AccountId,
   pub managers: Vec<AccountId>,
   pub title: String,
-  pub cause: String, // Campaign course ie food security, water, tree planting, etc
+  pub cause: String, // Campaign course id, ie food security, water, tree planting, etc
   pub start_date: String,
   pub end_date: String,
   pub description: String,
@@ -17,8 +33,9 @@ pub struct Campaign {
   pub current: u128,  // Campaign current amount
   pub current_usd: f64,  // Campaign current amount in usd
   pub token: String, // The targeted token
+  pub fee_bps: Option<u16>, // Overrides the platform fee for donations to this campaign
+  pub fees: u128, // Fees taken from its donations, current is net so current + fees is the gross raised
 
-  pub voters: Vec<AccountId>, // Temporary set of people who have donated to this campaign, on voting, the donor is stripped from this list, can't vote again.
   pub partner: Option<String>, // The winner of the campaign
   pub partners: HashMap<String, u64>, // Possible companies to effect the campaign -> ((Kenya redcross, 20), ...) company name and votes.
 
@@ -66,8 +83,9 @@ impl Campaign {
       current: 0,
       current_usd: 0.0,
       token,
+      fee_bps: None,
+      fees: 0,
       created_on: env::block_timestamp(),
-      voters: Vec::new(),
       partner: None,
       partners: HashMap::new(),
       start_year: <i32 as FromStr>::from_str(start_dates_[0].trim()).unwrap(),
@@ -80,17 +98,14 @@ impl Campaign {
     }
   }
 
-  pub fn get_voter(&mut self, v: AccountId) -> bool {
-    let exists = self.voters.iter().find(|k| k == &&v.clone());
-    if exists.is_some() {
-      self.voters.retain(|x| *x != v.clone());
-      return true;
-    }
-    false
+  // A campaign is active until the end of its end date
+  pub fn is_active(&self) -> bool {
+    let today = timestamp_to_date(env::block_timestamp());
+    (self.end_year, self.end_month, self.end_day) >= (today.year, today.month, today.day)
   }
 
-  pub fn add_voter(&mut self, v: AccountId){
-    self.voters.push(v);
+  pub fn is_manager(&self, account_id: &AccountId) -> bool {
+    &self.created_by == account_id || self.managers.contains(account_id)
   }
 }
 
@@ -110,6 +125,12 @@ impl Contract {
     end_dates: String,
     img: String,
   ) {
+    ensure(
+      self.campaigns.get(&id).is_none(),
+      ContractError::CampaignExists,
+    );
+    self.assert_active_cause(&cause);
+    let initial_storage = env::storage_usage();
     let campaign = Campaign::new(
       id.clone(),
       title,
@@ -125,41 +146,130 @@ impl Contract {
     );
     self.campaigns.insert(&id.clone(), &campaign);
     self.campaigns_count += 1;
+    self.charge_storage(&campaign.created_by, initial_storage);
+    log_campaign_created(RecordCreatedLog {
+      id,
+      created_by: campaign.created_by,
+      cause: campaign.cause,
+      token: campaign.token,
+      target: U128(campaign.target),
+    });
+  }
+
+  // Managers can edit the campaign details, amounts and dates stay as created
+  pub fn update_campaign(
+    &mut self,
+    id: String,
+    title: Option<String>,
+    description: Option<String>,
+    img: Option<String>,
+  ) {
+    let mut campaign = self.find_campaign(&id).or_panic();
+    let caller = env::predecessor_account_id();
+    ensure(campaign.is_manager(&caller), ContractError::NotAllowed);
+    if let Some(title) = title {
+      campaign.title = title;
+    }
+    if let Some(description) = description {
+      campaign.description = description;
+    }
+    if let Some(img) = img {
+      campaign.img = img;
+    }
+    self.campaigns.insert(&id, &campaign);
+    self.log_campaign_change(id, "details");
+  }
+
+  // Once the campaign has ended a manager or guardian settles the partner with the most votes
+  pub fn finalize_campaign(&mut self, id: String) -> String {
+    let caller = env::predecessor_account_id();
+    let mut campaign = self.find_campaign(&id).or_panic();
+    ensure(
+      campaign.is_manager(&caller) || self.is_guardian(&caller),
+      ContractError::NotAllowed,
+    );
+    ensure(campaign.partner.is_none(), ContractError::CampaignFinalised);
+    ensure(!campaign.is_active(), ContractError::CampaignNotEnded);
+    // Ties go to the alphabetically first partner so the result doesn't depend on map order
+    let (partner, votes) = campaign
+      .partners
+      .iter()
+      .max_by(|a, b| a.1.cmp(b.1).then_with(|| b.0.cmp(a.0)))
+      .map(|(partner, votes)| (partner.clone(), *votes))
+      .ok_or(ContractError::NoCandidates)
+      .or_panic();
+    campaign.partner = Some(partner.clone());
+    self.campaigns.insert(&id, &campaign);
+    log_campaign_finalised(CampaignFinalisedLog {
+      id,
+      partner: partner.clone(),
+      votes,
+    });
+    partner
   }
 
   pub fn get_campaign(&self, id: String) -> Option<Campaign> {
     self.campaigns.get(&id)
   }
 
+  // Donors who can still vote for the campaign partner
+  pub fn get_campaign_voters(&self, id: String, page: usize, limit: usize) -> Response<AccountId> {
+    let voters = self.campaign_voter_set(&id);
+    let start_index = (page - 1) * limit;
+
+    Response {
+      results: voters
+        .as_vector()
+        .iter()
+        .skip(start_index)
+        .take(limit)
+        .collect(),
+      count: voters.len(),
+    }
+  }
+
+  pub fn get_cause_campaigns(
+    &self,
+    cause_id: String,
+    page: usize,
+    limit: usize,
+  ) -> Response<Campaign> {
+    let start_index = (page - 1) * limit;
+
+    let campaigns: Vec<Campaign> = self
+      .campaigns
+      .values()
+      .filter(|campaign| campaign.cause == cause_id)
+      .skip(start_index)
+      .take(limit)
+      .collect();
+
+    Response {
+      results: campaigns,
+      count: self
+        .campaigns
+        .values()
+        .filter(|campaign| campaign.cause == cause_id)
+        .count() as u64,
+    }
+  }
+
   // pub fn get_campaigns(&self) -> Vec<Campaign> {
   //   self.campaigns.values().collect()
   // }
 
-  pub fn add_campaign_partner(&mut self, id: String, partner: String) -> String {
-    let campaign = self.get_campaign(id.clone());
-    if campaign.as_ref().is_some() {
-      let mut c = campaign.unwrap();
-      c.partners.insert(partner.clone(), 0);
-      self.campaigns.insert(&id.clone(), &c);
-      return "done".to_string();
-    }
-    return "not found".to_string();
-  }
-
-  pub fn campaign_vote(&mut self, id: String, partner: String) -> String {
-    let v = env::predecessor_account_id();
-    let campaign = self.get_campaign(id.clone());
-    if campaign.as_ref().is_some() {
-      let mut c = campaign.unwrap();
-      if c.get_voter(v) {
-        let votes = c.partners.get_mut(&partner.clone()).unwrap();
-        *votes += 1;
-        self.campaigns.insert(&id.clone(), &c);
-        return "done".to_string();
-      }
-      return "voter not found".to_string();
-    }
-    return "not found".to_string();
+  pub fn add_campaign_partner(&mut self, id: String, partner: String) {
+    self.internal_add_campaign_partner(&id, partner).or_panic()
+  }
+
+  pub fn campaign_vote(&mut self, id: String, partner: String) {
+    self.internal_campaign_vote(&id, partner).or_panic()
+  }
+
+  // Applies to votes cast from now on, votes already counted stay as they are
+  pub fn set_partner_voting(&mut self, partner_voting: PartnerVoting) {
+    self.assert_owner();
+    self.config.partner_voting = partner_voting;
   }
 
   pub fn filter_campaigns(&self, period: String, year: i32, month: i32) -> Vec<Campaign> {
@@ -208,3 +318,153 @@ impl Contract {
   }
 
 }
+
+impl Contract {
+  pub fn find_campaign(&self, id: &String) -> Result<Campaign, ContractError> {
+    self
+      .campaigns
+      .get(id)
+      .ok_or(ContractError::CampaignNotFound)
+  }
+
+  // Campaigns only take their own token, whether donated, matched or subscribed
+  pub fn campaign_in_token(&self, id: &String, token: &str) -> Result<Campaign, ContractError> {
+    let campaign = self.find_campaign(id)?;
+    if campaign.token != token {
+      return Err(ContractError::TokenMismatch);
+    }
+    Ok(campaign)
+  }
+
+  // Temporary set of people who have donated to this campaign, on voting, the donor is stripped from this set, can't vote again.
+  fn campaign_voter_set(&self, id: &String) -> UnorderedSet<AccountId> {
+    self.campaign_voters.get(id).unwrap_or_else(|| {
+      UnorderedSet::new(StorageKey::CampaignVoterSet {
+        campaign_hash: env::sha256(id.as_bytes()),
+      })
+    })
+  }
+
+  pub fn add_campaign_voter(&mut self, id: &String, voter: &AccountId) {
+    if self.has_voted(id, voter) {
+      return;
+    }
+    let mut voters = self.campaign_voter_set(id);
+    voters.insert(voter);
+    self.campaign_voters.insert(id, &voters);
+  }
+
+  // Anonymous donors can vote too, but are kept where they can't be listed
+  pub fn add_anonymous_campaign_voter(&mut self, id: &str, voter: &AccountId) {
+    if self.has_voted(id, voter) {
+      return;
+    }
+    self
+      .campaign_anonymous_donors
+      .insert(&(id.to_string(), voter.clone()), &true);
+  }
+
+  // Strips the voter from the set, None if they were not in it.
+  // Some(true) when they only gave anonymously, their vote is then logged as anonymous.
+  fn take_campaign_voter(&mut self, id: &String, voter: &AccountId) -> Option<bool> {
+    let mut voters = self.campaign_voter_set(id);
+    let listed = voters.remove(voter);
+    self.campaign_voters.insert(id, &voters);
+    let key = (id.clone(), voter.clone());
+    let anonymous = self.campaign_anonymous_donors.get(&key).unwrap_or(false);
+    if anonymous {
+      self.campaign_anonymous_donors.insert(&key, &false);
+    }
+    (listed || anonymous).then_some(!listed)
+  }
+
+  pub fn has_voted(&self, id: &str, voter: &AccountId) -> bool {
+    self
+      .campaign_voted
+      .contains(&(id.to_string(), voter.clone()))
+  }
+
+  // Gave to the campaign anonymously at least once
+  pub fn is_anonymous_campaign_donor(&self, id: &str, donor: &AccountId) -> bool {
+    self
+      .campaign_anonymous_donors
+      .contains_key(&(id.to_string(), donor.clone()))
+  }
+
+  pub fn partner_votes(&self, campaign: &Campaign, voter: &AccountId) -> u64 {
+    match self.config.partner_voting {
+      PartnerVoting::Equal => 1,
+      PartnerVoting::Weighted => {
+        let donated = self
+          .campaign_contributions
+          .get(&(campaign.id.clone(), voter.clone()))
+          .unwrap_or(0);
+        let decimals = self.token_decimals(&campaign.token);
+        let whole = donated / 10u128.pow(u32::from(decimals));
+        u64::try_from(whole).unwrap_or(u64::MAX).max(1)
+      }
+    }
+  }
+
+  pub fn log_campaign_change(&self, id: String, change: &str) {
+    log_campaign_updated(CampaignUpdatedLog {
+      id,
+      updated_by: env::predecessor_account_id(),
+      change: change.to_string(),
+    });
+  }
+
+  // Managers and guardians add candidates, one already standing keeps the votes it has
+  pub fn internal_add_campaign_partner(
+    &mut self,
+    id: &String,
+    partner: String,
+  ) -> Result<(), ContractError> {
+    let mut c = self.find_campaign(id)?;
+    let caller = env::predecessor_account_id();
+    if !c.is_manager(&caller) && !self.is_guardian(&caller) {
+      return Err(ContractError::NotAllowed);
+    }
+    self.find_partner(&partner)?;
+    if c.partners.contains_key(&partner) {
+      return Err(ContractError::PartnerAlreadyCandidate);
+    }
+    c.partners.insert(partner, 0);
+    self.campaigns.insert(id, &c);
+    self.log_campaign_change(id.clone(), "partner_added");
+    Ok(())
+  }
+
+  // Each donor votes once for one of the candidate partners, see PartnerVoting for the weight
+  pub fn internal_campaign_vote(
+    &mut self,
+    id: &String,
+    partner: String,
+  ) -> Result<(), ContractError> {
+    let mut c = self.find_campaign(id)?;
+    if c.partner.is_some() {
+      return Err(ContractError::CampaignFinalised);
+    }
+    if !c.partners.contains_key(&partner) {
+      return Err(ContractError::PartnerNotCandidate);
+    }
+    let voter = env::predecessor_account_id();
+    if self.has_voted(id, &voter) {
+      return Err(ContractError::AlreadyVoted);
+    }
+    let hidden = self
+      .take_campaign_voter(id, &voter)
+      .ok_or(ContractError::VoterNotEligible)?;
+    self.campaign_voted.insert(&(id.clone(), voter.clone()));
+    let votes = self.partner_votes(&c, &voter);
+    *c.partners.get_mut(&partner).unwrap() += votes;
+    self.campaigns.insert(id, &c);
+    log_vote_cast(VoteCastLog {
+      kind: "campaign".to_string(),
+      id: id.clone(),
+      partner,
+      voter: if hidden { anonymous() } else { voter },
+    });
+    Ok(())
+  }
+}
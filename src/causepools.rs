@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+
+use crate::errors::{ensure, ContractError, OrPanic};
+use crate::flags::FlagTarget;
+use crate::*;
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolBalance {
+  pub amount: U128,
+  pub amount_usd: f64,
+}
+
+impl PoolBalance {
+  // Takes an amount out along with its share of the USD value. Callers check the balance covers it,
+  // nothing taken from an empty balance would be valued at NaN.
+  pub fn take(&mut self, amount: u128) -> Result<f64, ContractError> {
+    if amount == 0 {
+      return Err(ContractError::ZeroAmount);
+    }
+    let amount_usd = self.amount_usd * amount as f64 / self.amount.0 as f64;
+    self.amount = U128(self.amount.0 - amount);
+    self.amount_usd -= amount_usd;
+    Ok(amount_usd)
+  }
+}
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CausePool {
+  pub cause: String,
+  pub balances: HashMap<String, PoolBalance>, // Unallocated funds per token
+  pub allocated: HashMap<String, PoolBalance>, // Funds already allocated to campaigns per token
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolShare {
+  pub campaign: String,
+  pub amount: U128,
+}
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolAllocation {
+  pub id: u64,
+  pub cause: String,
+  pub campaign: String,
+  pub token: String,
+  pub amount: U128,
+  pub amount_usd: f64, // Share of the pool's USD value at the time of allocation
+  pub allocated_by: AccountId,
+  pub created_on: Timestamp,
+}
+
+impl CausePool {
+  pub fn new(cause: String) -> Self {
+    Self {
+      cause,
+      balances: HashMap::new(),
+      allocated: HashMap::new(),
+    }
+  }
+}
+
+#[near_bindgen]
+impl Contract {
+  // Guardians split a cause pool across active campaigns of that cause
+  pub fn allocate_cause_pool(&mut self, cause_id: String, token: String, shares: Vec<PoolShare>) {
+    self.assert_guardian();
+    let mut pool = self
+      .cause_pools
+      .get(&cause_id)
+      .ok_or(ContractError::InsufficientPool)
+      .or_panic();
+    let mut balance = pool
+      .balances
+      .get(&token)
+      .cloned()
+      .ok_or(ContractError::InsufficientPool)
+      .or_panic();
+    let total: u128 = shares.iter().map(|share| share.amount.0).sum();
+    ensure(total <= balance.amount.0, ContractError::InsufficientPool);
+
+    let mut allocated = pool.allocated.get(&token).cloned().unwrap_or(PoolBalance {
+      amount: U128(0),
+      amount_usd: 0.0,
+    });
+    for share in shares {
+      let mut campaign = self
+        .pool_share_campaign(&cause_id, &token, &share.campaign)
+        .or_panic();
+
+      let amount_usd = balance.take(share.amount.0).or_panic();
+      allocated.amount = U128(allocated.amount.0 + share.amount.0);
+      allocated.amount_usd += amount_usd;
+
+      campaign.current += share.amount.0;
+      campaign.current_usd += amount_usd;
+      self.campaigns.insert(&share.campaign, &campaign);
+      self.log_campaign_change(share.campaign.clone(), "pool_allocation");
+
+      let allocation = PoolAllocation {
+        id: self.pool_allocations_count,
+        cause: cause_id.clone(),
+        campaign: share.campaign,
+        token: token.clone(),
+        amount: share.amount,
+        amount_usd,
+        allocated_by: env::predecessor_account_id(),
+        created_on: env::block_timestamp(),
+      };
+      self.pool_allocations.insert(&allocation.id, &allocation);
+      self.pool_allocations_count += 1;
+    }
+
+    pool.balances.insert(token.clone(), balance);
+    pool.allocated.insert(token, allocated);
+    self.cause_pools.insert(&cause_id, &pool);
+  }
+
+  pub fn get_cause_pool(&self, cause_id: String) -> Option<CausePool> {
+    self.cause_pools.get(&cause_id)
+  }
+
+  pub fn get_pool_allocations(
+    &self,
+    cause_id: String,
+    page: usize,
+    limit: usize,
+  ) -> Response<PoolAllocation> {
+    let start_index = (page - 1) * limit;
+
+    let allocations: Vec<PoolAllocation> = self
+      .pool_allocations
+      .values()
+      .filter(|allocation| allocation.cause == cause_id)
+      .skip(start_index)
+      .take(limit)
+      .collect();
+
+    Response {
+      results: allocations,
+      count: self
+        .pool_allocations
+        .values()
+        .filter(|allocation| allocation.cause == cause_id)
+        .count() as u64,
+    }
+  }
+}
+
+impl Contract {
+  pub fn credit_cause_pool(&mut self, cause_id: &str, token: &str, amount: u128, amount_usd: f64) {
+    let cause_id = cause_id.to_string();
+    let mut pool = self
+      .cause_pools
+      .get(&cause_id)
+      .unwrap_or(CausePool::new(cause_id.clone()));
+    let balance = pool
+      .balances
+      .entry(token.to_string())
+      .or_insert(PoolBalance {
+        amount: U128(0),
+        amount_usd: 0.0,
+      });
+    balance.amount = U128(balance.amount.0 + amount);
+    balance.amount_usd += amount_usd;
+    self.cause_pools.insert(&cause_id, &pool);
+  }
+
+  // Pools only go to active campaigns of their cause that take the pool's token, aren't frozen
+  // and aren't voting on milestone evidence
+  pub fn pool_share_campaign(
+    &self,
+    cause_id: &str,
+    token: &str,
+    campaign_id: &String,
+  ) -> Result<Campaign, ContractError> {
+    let campaign = self.find_campaign(campaign_id)?;
+    if campaign.cause != cause_id {
+      return Err(ContractError::CampaignNotInCause);
+    }
+    if campaign.token != token {
+      return Err(ContractError::TokenMismatch);
+    }
+    if !campaign.is_active() {
+      return Err(ContractError::CampaignEnded);
+    }
+    if self.is_campaign_cancelled(campaign_id) {
+      return Err(ContractError::CampaignCancelled);
+    }
+    if self.is_frozen(FlagTarget::Campaign {
+      id: campaign_id.clone(),
+    }) {
+      return Err(ContractError::Frozen);
+    }
+    if self.milestone_vote_open(campaign_id) {
+      return Err(ContractError::MilestoneVoteOpen);
+    }
+    Ok(campaign)
+  }
+}
@@ -1,26 +1,92 @@
 
 
+use crate::errors::{ensure, ContractError, OrPanic};
 use crate::*;
 
 #[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug)]
 #[serde(crate="near_sdk::serde")]
 pub struct Cause {
+    pub id: String,
     pub title: String, // name or title of the course Food Security, water
+    pub description: String,
+    pub icon: String,
+    pub active: bool, // Archived causes can't take new campaigns or events
+    pub created_by: AccountId,
+    pub created_on: Timestamp,
 }
 
 impl Cause{
-    
+    pub fn new(id: String, title: String, description: String, icon: String) -> Self {
+        Self {
+            id,
+            title,
+            description,
+            icon,
+            active: true,
+            created_by: env::predecessor_account_id(),
+            created_on: env::block_timestamp(),
+        }
+    }
 }
 
 #[near_bindgen]
 impl Contract{
-    pub fn create_cause(&mut self, title: String){
-        let cause = Cause{title};
-        self.causes.insert(&cause);
+    pub fn create_cause(&mut self, id: String, title: String, description: String, icon: String){
+        self.assert_guardian();
+        ensure(self.causes.get(&id).is_none(), ContractError::CauseExists);
+        let cause = Cause::new(id.clone(), title, description, icon);
+        self.causes.insert(&id, &cause);
         self.causes_count += 1;
     }
 
+    pub fn update_cause(&mut self, id: String, title: String, description: String, icon: String){
+        self.assert_guardian();
+        let mut cause = self.find_cause(&id).or_panic();
+        cause.title = title;
+        cause.description = description;
+        cause.icon = icon;
+        self.causes.insert(&id, &cause);
+    }
+
+    pub fn archive_cause(&mut self, id: String){
+        self.set_cause_active(id, false);
+    }
+
+    pub fn restore_cause(&mut self, id: String){
+        self.set_cause_active(id, true);
+    }
+
+    pub fn get_cause(&self, id: String) -> Option<Cause>{
+        self.causes.get(&id)
+    }
+
     pub fn get_causes(&self)->Vec<Cause>{
-        self.causes.iter().collect()
+        self.causes.values().collect()
+    }
+}
+
+impl Contract{
+    pub fn find_cause(&self, id: &String) -> Result<Cause, ContractError>{
+        self.causes.get(id).ok_or(ContractError::CauseNotFound)
+    }
+
+    fn set_cause_active(&mut self, id: String, active: bool){
+        self.assert_guardian();
+        let mut cause = self.find_cause(&id).or_panic();
+        cause.active = active;
+        self.causes.insert(&id, &cause);
     }
-}
\ No newline at end of file
+
+    // Campaigns and events must reference an existing, non archived cause
+    pub fn assert_active_cause(&self, id: &String){
+        self.active_cause(id).or_panic();
+    }
+
+    pub fn active_cause(&self, id: &String) -> Result<Cause, ContractError>{
+        let cause = self.find_cause(id)?;
+        if !cause.active {
+            return Err(ContractError::CauseArchived);
+        }
+        Ok(cause)
+    }
+}
@@ -1,6 +1,4 @@
-use near_sdk::{
-  AccountId, Gas, StorageUsage,
-};
+use near_sdk::{AccountId, Balance, Gas, StorageUsage};
 
 const U128_STORAGE: StorageUsage = 16;
 const U64_STORAGE: StorageUsage = 8;
@@ -24,10 +22,85 @@ pub const GAS_FOR_FT_TRANSFER_CALL: Gas = Gas(25_000_000_000_000 + 20_000_000_00
 
 pub const GAS_FOR_FT_TRANSFER: Gas = Gas(20_000_000_000_000);
 
+pub const GAS_FOR_FT_METADATA: Gas = Gas(10_000_000_000_000);
+
+pub const GAS_FOR_FT_STORAGE: Gas = Gas(10_000_000_000_000);
+
+// Token payout callbacks, each keeps enough gas for the stages after it
+pub const GAS_FOR_ON_PAYOUT_REGISTERED: Gas =
+  Gas(GAS_FOR_BASIC_OP.0 + GAS_FOR_FT_TRANSFER.0 + GAS_FOR_BASIC_OP.0);
+pub const GAS_FOR_ON_PAYOUT_STORAGE_CHECKED: Gas =
+  Gas(GAS_FOR_BASIC_OP.0 + 2 * GAS_FOR_FT_STORAGE.0 + GAS_FOR_ON_PAYOUT_REGISTERED.0);
+
+// Attached to storage_deposit for payout receivers, the usual NEP-145 minimum, any excess is refunded
+pub const FT_STORAGE_DEPOSIT: Balance = 1_250_000_000_000_000_000_000;
+
+// Kept by upgrade itself, the rest of the prepaid gas goes to migrate
+pub const GAS_FOR_UPGRADE: Gas = Gas(20_000_000_000_000);
+
 pub const EVENT: &str = "Events will have a registration process. There can be a cost associated with this or a simple request for the participant’s information. There can also be many registration types within an event.";
 pub const CAMPAIGN: &str = "Campaigns are typically longer running fundraisers. They can be to raise funds for a new building, quarterly budgets, food for a soup kitchen, scholarships, sponsorships and more!";
 pub const FROM_URL: &str = "https://info.givegab.com/blog/the-difference-between-campaigns-and-events/";
 
+// Layout version of the contract state, bump it with every layout change and migrate from the previous one
+pub const STATE_VERSION: u32 = 1;
+
+// Token id used for donations and balances in native NEAR
+pub const NEAR_TOKEN: &str = "near";
+
+pub const MAX_MESSAGE_LENGTH: usize = 280;
+pub const MAX_DEDICATION_LENGTH: usize = 100;
+
+// Basis points in 100%, the highest possible fee
+pub const MAX_FEE_BPS: u16 = 10_000;
+
+// Matching ratio that adds one token for every token donated
+pub const ONE_TO_ONE_BPS: u32 = 10_000;
+
+// Matching pools hold at least this many whole tokens, and a target takes a limited number of them
+pub const MIN_MATCHING_POOL_TOKENS: u128 = 1;
+pub const MAX_MATCHING_POOLS_PER_TARGET: usize = 10;
+
+// Storage a sponsor keeps available for each match, the record and its stats take about 650 bytes with short ids
+pub const MATCH_DONATION_STORAGE: StorageUsage = 1_000;
+
+// Matches are stored as "{donation id}:match:{pool id}", donors can't use ids of that form
+pub const MATCH_ID_SEPARATOR: &str = ":match:";
+
+// Shortest time between two subscription donations, one day
+pub const MIN_SUBSCRIPTION_INTERVAL: u64 = 86_400_000_000_000;
+
+// Storage a donor keeps available for each subscription donation, the record and its stats.
+// A first donation to a campaign takes about 1_050 bytes with a 64 byte donor id, matches are paid by their sponsors.
+pub const SUBSCRIPTION_DONATION_STORAGE: StorageUsage = 2_000;
+
+// Storage added for each open round a subscription donation counts towards, on top of its campaign and donor ids.
+// A contribution that also opens the campaign's tally takes about 210 bytes besides the ids, the campaign id is stored twice.
+pub const ROUND_CONTRIBUTION_STORAGE: StorageUsage = 250;
+
+// Subscription donations are stored as "sub:{subscription id}:{donation}", donor ids can't start with it
+pub const SUBSCRIPTION_ID_PREFIX: &str = "sub:";
+
+// Shortest time donors get to vote on milestone evidence, one day
+pub const MIN_MILESTONE_VOTE_PERIOD: u64 = 86_400_000_000_000;
+
+// Config of states migrated from the unversioned contract, the owner can change it afterwards
+pub const DEFAULT_GRANT_QUORUM: u64 = 1;
+pub const DEFAULT_UPGRADE_QUORUM: u64 = 1;
+pub const DEFAULT_MILESTONE_QUORUM_BPS: u16 = 2_000;
+pub const DEFAULT_MILESTONE_THRESHOLD_BPS: u16 = 5_000;
+pub const DEFAULT_MILESTONE_VOTE_PERIOD: u64 = 7 * 86_400_000_000_000;
+
+// Attached to a flag to deter spam, 0.1 NEAR
+pub const FLAG_BOND: Balance = 100_000_000_000_000_000_000_000;
+
+pub const ANONYMOUS_DONOR: &str = "anonymous";
+
+// Placeholder shown instead of the donor on anonymous donations
+pub fn anonymous() -> AccountId {
+  ANONYMOUS_DONOR.to_string().try_into().unwrap()
+}
+
 // All the accounts that we may need during testing
 pub fn supercode() -> AccountId {
   "supercode.testnet".to_string().try_into().unwrap()
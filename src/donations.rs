@@ -1,5 +1,59 @@
+use crate::constants::{
+  anonymous, MATCH_ID_SEPARATOR, MAX_DEDICATION_LENGTH, MAX_FEE_BPS, MAX_MESSAGE_LENGTH,
+  NEAR_TOKEN, SUBSCRIPTION_ID_PREFIX,
+};
+use crate::errors::{ensure, ContractError, OrPanic};
+use crate::fees::fee_amount;
+use crate::flags::FlagTarget;
+use crate::logs::{log_donation_received, DonationReceivedLog};
 use crate::*;
 
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "lowercase")]
+pub enum DedicationKind {
+  Honour,
+  Memory,
+}
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Dedication {
+  pub kind: DedicationKind, // In honour of or in memory of
+  pub name: String,         // Who the donation is dedicated to
+}
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum DonationTarget {
+  General,                 // General fund treasury
+  Campaign { id: String }, // A campaign
+  Event { id: String },    // An event
+  Cause { id: String },    // A cause pool
+  #[serde(other)]
+  Unknown, // Any other type, parsed so it can be refused with UnknownTarget
+}
+
+impl DonationTarget {
+  // Target from the old target/campaign/event strings where "null" means none
+  pub fn from_legacy(
+    target: &str,
+    campaign: Option<String>,
+    event: Option<String>,
+    cause: Option<String>,
+  ) -> Option<Self> {
+    let some = |id: Option<String>| id.filter(|id| id != "null");
+    match target {
+      "general" => Some(DonationTarget::General),
+      "campaign" => some(campaign).map(|id| DonationTarget::Campaign { id }),
+      "event" => some(event).map(|id| DonationTarget::Event { id }),
+      "cause" => some(cause).map(|id| DonationTarget::Cause { id }),
+      _ => None,
+    }
+  }
+}
+
 #[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug)]
 #[serde(crate = "near_sdk::serde")]
 pub struct DonationDetails {
@@ -7,120 +61,156 @@ pub struct DonationDetails {
   pub tokenmetadata: Option<TokenMetadata>,
 }
 
-#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug)]
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone)]
 #[serde(crate = "near_sdk::serde")]
 pub struct Donation {
   pub id: String,
   pub donor: AccountId,
   pub token: String,
-  pub amount: u128,             // Amount in token value
-  pub amount_usd: f64,          // Amount in USD
-  pub target: String,           // Target - is either; general, event, or campaign
-  pub event: Option<String>,    // Any associated event ID
-  pub campaign: Option<String>, // Any associated campaign ID
+  pub amount: u128,            // Amount in token value, gross of the platform fee
+  pub fee: u128,               // Platform fee taken from the amount
+  pub net: u128,               // Amount credited to the target
+  pub tip: u128,               // Sent on top of the amount for the platform
+  pub amount_usd: f64,         // Amount in USD
+  pub target: DonationTarget,  // General fund, campaign, event or cause
+  pub anonymous: bool,         // Donor is hidden from public listings
+  pub message: Option<String>, // Note from the donor
+  pub dedication: Option<Dedication>,
+  pub message_hidden: bool, // Message hidden by a guardian or campaign/event manager
   pub created_at: Timestamp,
 }
 
+// A donation as it comes in, before the platform fee is worked out
+pub struct DonationInput {
+  pub id: String,
+  pub donor: AccountId,
+  pub token: String,
+  pub amount: U128,
+  pub tip: u128,
+  pub amount_usd: f64,
+  pub target: DonationTarget,
+  pub anonymous: bool,
+  pub message: Option<String>,
+  pub dedication: Option<Dedication>,
+}
+
 impl Donation {
-  pub fn new(
-    id: String,
-    donor: AccountId,
-    token: String,
-    amount: U128,
-    amount_usd: f64,
-    target: String,
-    event: Option<String>,
-    campaign: Option<String>,
-  ) -> Self {
+  pub fn new(input: DonationInput, fee: u128) -> Self {
+    check_note(&input.message, &input.dedication).or_panic();
     Self {
-      id,
-      donor,
-      token,
-      amount: u128::from(amount),
-      amount_usd,
-      target,
-      event,
-      campaign,
+      id: input.id,
+      donor: input.donor,
+      token: input.token,
+      amount: input.amount.0,
+      fee,
+      net: input.amount.0 - fee,
+      tip: input.tip,
+      amount_usd: input.amount_usd,
+      target: input.target,
+      anonymous: input.anonymous,
+      message: input.message,
+      dedication: input.dedication,
+      message_hidden: false,
       created_at: env::block_timestamp(),
     }
   }
-}
 
-#[near_bindgen]
-impl Contract {
-  pub fn create_donation(
-    &mut self,
-    id: String,
-    donor: AccountId,
-    token: String,
-    amount: U128,
-    amount_usd: f64,
-    target: String,
-    event: String,
-    campaign: String,
-  ) {
-    let mut cid = Some(campaign.clone());
-    if campaign == "null".to_string() {
-      cid = None
-    }
-    let mut eid = Some(event.clone());
-    if event == "null".to_string() {
-      eid = None
+  // Public listings never show who made an anonymous donation or a hidden message
+  pub fn redacted(self) -> Self {
+    let message = if self.message_hidden {
+      None
+    } else {
+      self.message
+    };
+    if self.anonymous {
+      return Self {
+        donor: anonymous(),
+        message,
+        ..self
+      };
     }
+    Self { message, ..self }
+  }
+}
 
-    let donation = Donation::new(
-      id,
-      donor.clone(),
-      token,
-      amount.clone(),
-      amount_usd.clone(),
-      target.clone(),
-      eid,
-      cid,
-    );
-    self.donations.insert(&donation);
-    self.total_usd += amount_usd;
-    self.donations_count += 1;
-    if target.clone() == "event".to_string() {
-      let mut event_itself = self.get_event(event.clone()).unwrap();
-      event_itself.add_voter(donor.clone());
-      event_itself.current += u128::from(amount.clone());
-      event_itself.current_usd += amount_usd.clone();
-      self.events.insert(&event.clone(), &event_itself);
-    } else if target == "campaign".to_string() {
-      let mut campaign_itself = self.get_campaign(campaign.clone()).unwrap();
-      campaign_itself.add_voter(donor.clone());
-      campaign_itself.current += u128::from(amount.clone());
-      campaign_itself.current_usd += amount_usd;
-      self.campaigns.insert(&campaign.clone(), &campaign_itself);
-    }
+// The donor's message and dedication have to fit their limits
+pub fn check_note(
+  message: &Option<String>,
+  dedication: &Option<Dedication>,
+) -> Result<(), ContractError> {
+  if message
+    .as_ref()
+    .is_some_and(|message| message.len() > MAX_MESSAGE_LENGTH)
+  {
+    return Err(ContractError::MessageTooLong);
+  }
+  if dedication
+    .as_ref()
+    .is_some_and(|dedication| dedication.name.len() > MAX_DEDICATION_LENGTH)
+  {
+    return Err(ContractError::DedicationTooLong);
+  }
+  Ok(())
+}
+
+// Targets of a type the contract does not know are refused wherever one is given
+pub fn check_target(target: &DonationTarget) -> Result<(), ContractError> {
+  if *target == DonationTarget::Unknown {
+    return Err(ContractError::UnknownTarget);
+  }
+  Ok(())
+}
+
+// Ids donors choose must not take the form of the ids the contract gives matches and subscription donations
+pub fn check_donation_id(id: &str) -> Result<(), ContractError> {
+  if id.contains(MATCH_ID_SEPARATOR) || id.starts_with(SUBSCRIPTION_ID_PREFIX) {
+    return Err(ContractError::ReservedDonationId);
   }
+  Ok(())
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DonorTotal {
+  pub donor: AccountId, // "anonymous" for all anonymous donations together
+  pub amount: U128,
+  pub amount_usd: f64,
+  pub donations: u64,
+}
 
+#[near_bindgen]
+impl Contract {
   #[payable]
   pub fn near_donation(
     &mut self,
     id: String,
-    token: String,
     amount: U128,
     amount_usd: f64,
-    target: String,
-    event: String,
-    campaign: String,
+    target: DonationTarget,
+    anonymous: Option<bool>,
+    message: Option<String>,
+    dedication: Option<Dedication>,
+    tip: Option<U128>,
   ) {
-    // Register donations made in near
-    let donor = env::predecessor_account_id();
-    self.create_donation(
+    // Register donations made in near, the deposit covers the amount and the tip
+    check_donation_id(&id).or_panic();
+    let tip = tip.map_or(0, |tip| tip.0);
+    ensure(
+      env::attached_deposit() == amount.0 + tip,
+      ContractError::DepositNotAmount,
+    );
+    self.create_donation(DonationInput {
       id,
-      donor.clone(),
-      token,
+      donor: env::predecessor_account_id(),
+      token: NEAR_TOKEN.to_string(),
       amount,
+      tip,
       amount_usd,
-      target.clone(),
-      event.clone(),
-      campaign.clone(),
-    );
-    
-    env::attached_deposit();
+      target,
+      anonymous: anonymous.unwrap_or(false),
+      message,
+      dedication,
+    });
   }
 
   pub fn get_campaign_donations(
@@ -133,33 +223,32 @@ impl Contract {
 
     let donations: Vec<Donation> = self
       .donations
-      .iter()
-      .filter(|don| don.campaign == Some(id.clone()))
+      .values()
+      .filter(|don| don.target == DonationTarget::Campaign { id: id.clone() })
       .skip(start_index)
       .take(limit)
       .collect();
 
     let donations_ = self
       .donations
-      .iter()
-      .filter(|don| don.campaign == Some(id.clone()))
+      .values()
+      .filter(|don| don.target == DonationTarget::Campaign { id: id.clone() })
       .count();
 
     let mut results: Vec<DonationDetails> = Vec::new();
 
     for don in donations {
-      let metadata = self.get_token(don.token.clone());
+      let metadata = self.token_metadata(&don.token);
       let details = DonationDetails {
-        donation: don,
+        donation: don.redacted(),
         tokenmetadata: metadata,
       };
       results.push(details);
     }
-    let response = Response {
+    Response {
       results,
       count: donations_ as u64,
-    };
-    return response;
+    }
   }
 
   pub fn get_event_donations(
@@ -172,33 +261,156 @@ impl Contract {
 
     let donations: Vec<Donation> = self
       .donations
-      .iter()
-      .filter(|don| don.event == Some(id.clone()))
+      .values()
+      .filter(|don| don.target == DonationTarget::Event { id: id.clone() })
       .skip(start_index)
       .take(limit)
       .collect();
 
     let donations_ = self
       .donations
-      .iter()
-      .filter(|don| don.event == Some(id.clone()))
+      .values()
+      .filter(|don| don.target == DonationTarget::Event { id: id.clone() })
+      .count();
+
+    let mut results: Vec<DonationDetails> = Vec::new();
+
+    for don in donations {
+      let metadata = self.token_metadata(&don.token);
+      let details = DonationDetails {
+        donation: don.redacted(),
+        tokenmetadata: metadata,
+      };
+      results.push(details);
+    }
+    Response {
+      results,
+      count: donations_ as u64,
+    }
+  }
+
+  // Donation history of a donor. Anonymous donations are left out, listing them under the donor,
+  // even redacted, would tell who made them. Donors see them with get_own_donations.
+  pub fn get_donor_donations(
+    &self,
+    donor: AccountId,
+    page: usize,
+    limit: usize,
+  ) -> Response<DonationDetails> {
+    let start_index = (page - 1) * limit;
+
+    let donations: Vec<Donation> = self
+      .donations
+      .values()
+      .filter(|don| don.donor == donor && !don.anonymous)
+      .skip(start_index)
+      .take(limit)
+      .collect();
+
+    let donations_ = self
+      .donations
+      .values()
+      .filter(|don| don.donor == donor && !don.anonymous)
       .count();
 
     let mut results: Vec<DonationDetails> = Vec::new();
 
     for don in donations {
-      let metadata = self.get_token(don.token.clone());
+      let metadata = self.token_metadata(&don.token);
       let details = DonationDetails {
-        donation: don,
+        donation: don.redacted(),
         tokenmetadata: metadata,
       };
       results.push(details);
     }
-    let response = Response {
+    Response {
       results,
       count: donations_ as u64,
+    }
+  }
+
+  // The caller's own donation history, anonymous donations included. A call rather than a view,
+  // so the listing only goes to the donor.
+  pub fn get_own_donations(&mut self, page: usize, limit: usize) -> Response<DonationDetails> {
+    let donor = env::predecessor_account_id();
+    let start_index = (page - 1) * limit;
+
+    let donations: Vec<Donation> = self
+      .donations
+      .values()
+      .filter(|don| don.donor == donor)
+      .collect();
+
+    let results = donations
+      .iter()
+      .skip(start_index)
+      .take(limit)
+      .map(|don| DonationDetails {
+        tokenmetadata: self.token_metadata(&don.token),
+        donation: Donation {
+          donor: donor.clone(),
+          ..don.clone().redacted()
+        },
+      })
+      .collect();
+    Response {
+      results,
+      count: donations.len() as u64,
+    }
+  }
+
+  pub fn get_donation(&self, id: String) -> Option<DonationDetails> {
+    self.donations.get(&id).map(|don| DonationDetails {
+      tokenmetadata: self.token_metadata(&don.token),
+      donation: don.redacted(),
+    })
+  }
+
+  // Guardians, and managers of the receiving campaign or event, can hide abusive messages
+  pub fn hide_donation_message(&mut self, id: String, hidden: bool) {
+    let mut donation = self
+      .donations
+      .get(&id)
+      .ok_or(ContractError::DonationNotFound)
+      .or_panic();
+    let caller = env::predecessor_account_id();
+    let manager = match donation.target.clone() {
+      DonationTarget::Campaign { id } => self
+        .get_campaign(id)
+        .is_some_and(|campaign| campaign.is_manager(&caller)),
+      DonationTarget::Event { id } => self
+        .get_event(id)
+        .is_some_and(|event| event.is_manager(&caller)),
+      _ => false,
     };
-    return response;
+    ensure(
+      self.is_guardian(&caller) || manager,
+      ContractError::NotAllowed,
+    );
+    donation.message_hidden = hidden;
+    self.donations.insert(&id, &donation);
+  }
+
+  pub fn get_campaign_leaderboard(&self, id: String, limit: usize) -> Vec<DonorTotal> {
+    self.leaderboard(
+      self
+        .donations
+        .values()
+        .filter(|don| don.target == DonationTarget::Campaign { id: id.clone() })
+        .collect(),
+      limit,
+    )
+  }
+
+  pub fn get_event_leaderboard(&self, id: String, limit: usize) -> Vec<DonorTotal> {
+    self.leaderboard(
+      self
+        .donations
+        .values()
+        .filter(|don| don.target == DonationTarget::Event { id: id.clone() })
+        .collect(),
+      limit,
+    )
   }
 
   pub fn token_donation(&mut self) {
@@ -206,3 +418,134 @@ impl Contract {
     // This functionality is done directly in fungible token file at deposit_tokens
   }
 }
+
+impl Contract {
+  // Only reachable through near_donation and the token receiver, which hold the actual funds.
+  // The donor's storage deposit pays for the record.
+  pub fn create_donation(&mut self, input: DonationInput) {
+    let initial_storage = env::storage_usage();
+    let donor = input.donor.clone();
+    let donation = self.record_donation(input);
+    self.add_round_contribution(&donation);
+    self.charge_storage(&donor, initial_storage);
+    // Sponsors pay for the records of their matches
+    self.match_donation(&donation);
+  }
+
+  // Stores the donation and credits its target, storage is charged by the caller
+  pub fn record_donation(&mut self, input: DonationInput) -> Donation {
+    ensure(
+      self.donations.get(&input.id).is_none(),
+      ContractError::DonationExists,
+    );
+    let fee_bps = self.fee_bps_for(&input.target);
+    let fee = fee_amount(input.amount.0, fee_bps);
+    // USD value credited to the target, the donor's own totals stay gross
+    let net_usd =
+      input.amount_usd - input.amount_usd * f64::from(fee_bps) / f64::from(MAX_FEE_BPS);
+
+    let donation = Donation::new(input, fee);
+    let donor = donation.donor.clone();
+    let anonymous = donation.anonymous;
+    let tip = donation.tip;
+    self.donations.insert(&donation.id, &donation);
+    self.total_usd += donation.amount_usd;
+    self.donations_count += 1;
+    if anonymous {
+      self.anonymous_donations_count += 1;
+    }
+    let stats_cause = match donation.target.clone() {
+      DonationTarget::Event { id: event } => {
+        let mut event_itself = self.find_event(&event).or_panic();
+        self.assert_not_frozen(FlagTarget::Event { id: event.clone() });
+        if anonymous {
+          self.add_anonymous_event_voter(&event, &donor);
+        } else {
+          self.add_event_voter(&event, &donor);
+        }
+        event_itself.current += donation.net;
+        event_itself.current_usd += net_usd;
+        self.events.insert(&event.clone(), &event_itself);
+        Some(event_itself.cause)
+      }
+      DonationTarget::Campaign { id: campaign } => {
+        let mut campaign_itself = self
+          .campaign_in_token(&campaign, &donation.token)
+          .or_panic();
+        self.assert_not_frozen(FlagTarget::Campaign {
+          id: campaign.clone(),
+        });
+        ensure(
+          !self.milestone_vote_open(&campaign),
+          ContractError::MilestoneVoteOpen,
+        );
+        if anonymous {
+          self.add_anonymous_campaign_voter(&campaign, &donor);
+        } else {
+          self.add_campaign_voter(&campaign, &donor);
+        }
+        self.add_campaign_contribution(&campaign, &donor, donation.net);
+        campaign_itself.current += donation.net;
+        campaign_itself.current_usd += net_usd;
+        campaign_itself.fees += fee;
+        self.campaigns.insert(&campaign.clone(), &campaign_itself);
+        Some(campaign_itself.cause)
+      }
+      DonationTarget::Cause { id: cause_id } => {
+        self.assert_active_cause(&cause_id);
+        self.credit_cause_pool(&cause_id, &donation.token, donation.net, net_usd);
+        Some(cause_id)
+      }
+      DonationTarget::General => {
+        self.credit_treasury(&donation.token, donation.net, net_usd);
+        None
+      }
+      DonationTarget::Unknown => ContractError::UnknownTarget.panic(),
+    };
+    self.credit_fees(&donation.token, fee + tip);
+    if tip > 0 {
+      self.tips_count += 1;
+      // amount_usd is for the amount alone, the tip is valued at the same rate
+      if donation.amount > 0 {
+        self.total_tips_usd += donation.amount_usd * tip as f64 / donation.amount as f64;
+      }
+    }
+    self.record_donation_stats(&donation, stats_cause);
+
+    let logged = donation.clone().redacted();
+    log_donation_received(DonationReceivedLog {
+      donation_id: logged.id,
+      donor: logged.donor,
+      token: logged.token,
+      amount: U128(logged.amount),
+      fee: U128(logged.fee),
+      tip: U128(logged.tip),
+      amount_usd: logged.amount_usd,
+      target: logged.target,
+    });
+    donation
+  }
+
+  // Ranks donors by USD donated, anonymous donations are grouped as a single "anonymous" donor
+  fn leaderboard(&self, donations: Vec<Donation>, limit: usize) -> Vec<DonorTotal> {
+    let mut totals: Vec<DonorTotal> = Vec::new();
+    for don in donations {
+      let don = don.redacted();
+      match totals.iter_mut().find(|total| total.donor == don.donor) {
+        Some(total) => {
+          total.amount = U128(total.amount.0 + don.amount);
+          total.amount_usd += don.amount_usd;
+          total.donations += 1;
+        }
+        None => totals.push(DonorTotal {
+          donor: don.donor,
+          amount: U128(don.amount),
+          amount_usd: don.amount_usd,
+          donations: 1,
+        }),
+      }
+    }
+    totals.sort_by(|a, b| b.amount_usd.partial_cmp(&a.amount_usd).unwrap());
+    totals.into_iter().take(limit).collect()
+  }
+}
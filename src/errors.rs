@@ -1,3 +1,5 @@
+use near_sdk::env;
+
 pub const ERR1_ACC_NOT_REGISTERED: &str = "E1: account not registered";
 pub const ERR2_INSUFFICIENT_STORAGE: &str = "E2: insufficient $NEAR storage deposit";
 pub const ERR3_NO_STORAGE_CAN_WITHDRAW: &str = "E3: no storage can withdraw";
@@ -9,3 +11,317 @@ pub const ERR7_INSUFFICIENT_LP_SHARES: &str = "E7: insufficient lp shares";
 pub const ERR8_AT_LEAST_ONE_YOCTO: &str = "E8: requires attached deposit of at least 1 yoctoNEAR";
 
 pub const ERR9_NOT_ALLOWED: &str = "E9: no permission to invoke this";
+pub const ERR10_MESSAGE_TOO_LONG: &str = "E10: donation message too long";
+pub const ERR11_DONATION_EXISTS: &str = "E11: donation id already used";
+pub const ERR12_DONATION_NOT_FOUND: &str = "E12: donation not found";
+pub const ERR13_INVALID_MSG: &str = "E13: invalid transfer msg";
+pub const ERR14_CAUSE_NOT_FOUND: &str = "E14: cause not found";
+pub const ERR15_CAUSE_ARCHIVED: &str = "E15: cause is archived";
+pub const ERR16_CAUSE_EXISTS: &str = "E16: cause id already used";
+pub const ERR17_CAMPAIGN_NOT_IN_CAUSE: &str = "E17: campaign does not belong to this cause";
+pub const ERR18_CAMPAIGN_ENDED: &str = "E18: campaign has ended";
+pub const ERR19_INSUFFICIENT_POOL: &str = "E19: allocation exceeds cause pool balance";
+pub const ERR20_CAMPAIGN_NOT_FOUND: &str = "E20: campaign not found";
+pub const ERR21_DEPOSIT_NOT_AMOUNT: &str = "E21: attached deposit must equal the donated amount";
+pub const ERR22_PARTNER_NOT_FOUND: &str = "E22: partner not found";
+pub const ERR23_PARTNER_NOT_VERIFIED: &str = "E23: partner is not verified";
+pub const ERR24_INSUFFICIENT_TREASURY: &str = "E24: amount exceeds treasury balance";
+pub const ERR25_GRANT_NOT_FOUND: &str = "E25: grant proposal not found";
+pub const ERR26_GRANT_NOT_PENDING: &str = "E26: grant proposal is not awaiting approval";
+pub const ERR27_ALREADY_APPROVED: &str = "E27: already approved by this guardian";
+pub const ERR28_GRANT_NOT_APPROVED: &str = "E28: grant proposal has not reached quorum";
+pub const ERR29_UNKNOWN_TARGET: &str = "E29: unknown donation target";
+pub const ERR30_CAMPAIGN_EXISTS: &str = "E30: campaign id already used";
+pub const ERR31_EVENT_NOT_FOUND: &str = "E31: event not found";
+pub const ERR32_EVENT_EXISTS: &str = "E32: event id already used";
+pub const ERR33_PARTNER_EXISTS: &str = "E33: partner id already used";
+pub const ERR34_PARTNER_NOT_CANDIDATE: &str = "E34: partner is not a candidate";
+pub const ERR35_VOTER_NOT_ELIGIBLE: &str = "E35: only donors who have not voted yet can vote";
+pub const ERR36_CAMPAIGN_NOT_ENDED: &str = "E36: campaign has not ended yet";
+pub const ERR37_NO_CANDIDATES: &str = "E37: campaign has no candidate partners";
+pub const ERR38_CAMPAIGN_FINALISED: &str = "E38: campaign partner already chosen";
+pub const ERR39_STORAGE_IN_USE: &str = "E39: account still pays for records, unregister with force";
+pub const ERR40_STATE_NOT_FOUND: &str = "E40: no contract state to migrate";
+pub const ERR41_NO_STAGED_CODE: &str = "E41: no contract code staged";
+pub const ERR42_CODE_HASH_MISMATCH: &str = "E42: staged code hash does not match";
+pub const ERR43_UPGRADE_NOT_APPROVED: &str = "E43: staged code has not reached quorum";
+pub const ERR44_TOKEN_NOT_ACCEPTED: &str = "E44: token is not accepted for donations";
+pub const ERR45_TOKEN_NOT_FOUND: &str = "E45: token not found";
+pub const ERR46_FEE_TOO_HIGH: &str = "E46: fee is more than 10000 basis points";
+pub const ERR47_TIP_TOO_HIGH: &str = "E47: tip must be less than the amount sent";
+pub const ERR48_MATCHING_TARGET: &str = "E48: matching pools are only for campaigns and causes";
+pub const ERR49_MATCHING_POOL_NOT_FOUND: &str = "E49: matching pool not found";
+pub const ERR50_INVALID_MATCHING_RATIO: &str = "E50: matching ratio must be more than 0";
+pub const ERR51_MATCHING_POOL_EMPTY: &str = "E51: matching pool has no balance";
+pub const ERR52_ROUND_NOT_FOUND: &str = "E52: round not found";
+pub const ERR53_INVALID_ROUND_WINDOW: &str = "E53: round must end after it starts";
+pub const ERR54_ROUND_NOT_ENDED: &str = "E54: round has not ended yet";
+pub const ERR55_ROUND_FINALISED: &str = "E55: round already finalised";
+pub const ERR56_SUBSCRIPTION_NOT_FOUND: &str = "E56: subscription not found";
+pub const ERR57_SUBSCRIPTION_TARGET: &str = "E57: subscriptions are only for active campaigns, causes and general donations";
+pub const ERR58_INVALID_SUBSCRIPTION: &str = "E58: subscription needs an amount, a deposit covering it and an interval of at least a day";
+pub const ERR59_SUBSCRIPTION_ACTIVE: &str = "E59: cancel the subscription before withdrawing";
+pub const ERR60_SUBSCRIPTION_EMPTY: &str = "E60: subscription has no balance";
+pub const ERR61_INVALID_MILESTONES: &str = "E61: milestone shares must add up to 10000 basis points";
+pub const ERR62_MILESTONES_LOCKED: &str = "E62: milestones can not change once the campaign has funds";
+pub const ERR63_MILESTONE_NOT_FOUND: &str = "E63: milestone not found";
+pub const ERR64_MILESTONE_NOT_PENDING: &str = "E64: milestone evidence already submitted";
+pub const ERR65_MILESTONE_NOT_SUBMITTED: &str = "E65: milestone has no evidence awaiting review";
+pub const ERR66_MILESTONE_NOT_APPROVED: &str = "E66: milestone has not been approved";
+pub const ERR67_CAMPAIGN_NOT_FINALISED: &str = "E67: campaign partner not chosen yet";
+pub const ERR68_CAMPAIGN_CANCELLED: &str = "E68: campaign was cancelled";
+pub const ERR69_CAMPAIGN_NOT_CANCELLED: &str = "E69: campaign was not cancelled";
+pub const ERR70_NOTHING_TO_REFUND: &str = "E70: nothing to refund";
+pub const ERR71_MILESTONE_VOTE_CLOSED: &str = "E71: milestone vote has closed";
+pub const ERR72_MILESTONE_VOTE_OPEN: &str = "E72: milestone vote is still open";
+pub const ERR73_INVALID_VOTING: &str = "E73: quorum and threshold must be between 1 and 10000 basis points";
+pub const ERR74_FLAG_NOT_FOUND: &str = "E74: flag not found";
+pub const ERR75_FLAG_BOND: &str = "E75: attach exactly the flag bond";
+pub const ERR76_FLAG_RESOLVED: &str = "E76: flag already resolved";
+pub const ERR77_FROZEN: &str = "E77: frozen while a flag is under review";
+pub const ERR78_DEDICATION_TOO_LONG: &str = "E78: dedication name is too long";
+pub const ERR79_TOKEN_MISMATCH: &str = "E79: token is not the one the campaign takes";
+pub const ERR80_UNKNOWN_STATE: &str = "E80: state is in a layout migrate does not know";
+pub const ERR81_INVALID_QUORUM: &str = "E81: quorum must be at least one guardian";
+pub const ERR82_INVALID_TOKEN_ADDRESS: &str = "E82: token address is not a valid account id";
+pub const ERR83_TOKEN_ADDRESS_MISMATCH: &str = "E83: metadata address is not the token being added";
+pub const ERR84_MATCHING_POOL_TOO_SMALL: &str = "E84: matching pool must hold at least one whole token";
+pub const ERR85_MATCHING_POOL_LIMIT: &str = "E85: target already has the most matching pools allowed";
+pub const ERR86_RESERVED_DONATION_ID: &str = "E86: donation id uses a form reserved for matches and subscriptions";
+pub const ERR87_NOTHING_TO_RELEASE: &str = "E87: campaign has no funds left to release";
+pub const ERR88_MILESTONES_OUTSTANDING: &str = "E88: campaign funds are released by its milestones";
+pub const ERR89_MILESTONE_VOTE_NOT_FOUND: &str = "E89: milestone has no vote, its evidence was settled or never submitted";
+pub const ERR90_VOTE_PERIOD_TOO_SHORT: &str = "E90: milestone vote period must be at least a day";
+pub const ERR91_NO_FLAG_BOND: &str = "E91: flag has no bond left to return";
+pub const ERR92_FLAG_NOT_RESOLVED: &str = "E92: flag is still under review";
+pub const ERR93_ALREADY_VOTED: &str = "E93: donor already voted for a partner of this campaign";
+pub const ERR94_ZERO_AMOUNT: &str = "E94: amount must be more than zero";
+pub const ERR95_ONE_YOCTO: &str = "E95: requires an attached deposit of exactly 1 yoctoNEAR";
+pub const ERR96_UPGRADE_GAS: &str = "E96: not enough gas attached to migrate after the upgrade";
+pub const ERR97_PARTNER_ALREADY_CANDIDATE: &str = "E97: partner is already a candidate";
+
+// Every failure the contract reports, each one maps to a coded message above
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContractError {
+  AccNotRegistered,
+  InsufficientStorage,
+  NoStorageCanWithdraw,
+  StorageWithdrawTooMuch,
+  DepositLessThanMinStorage,
+  TransferToSelf,
+  InsufficientLpShares,
+  NotAllowed,
+  MessageTooLong,
+  DonationExists,
+  DonationNotFound,
+  InvalidMsg,
+  CauseNotFound,
+  CauseArchived,
+  CauseExists,
+  CampaignNotInCause,
+  CampaignEnded,
+  InsufficientPool,
+  CampaignNotFound,
+  DepositNotAmount,
+  PartnerNotFound,
+  PartnerNotVerified,
+  InsufficientTreasury,
+  GrantNotFound,
+  GrantNotPending,
+  AlreadyApproved,
+  GrantNotApproved,
+  UnknownTarget,
+  CampaignExists,
+  EventNotFound,
+  EventExists,
+  PartnerExists,
+  PartnerNotCandidate,
+  VoterNotEligible,
+  CampaignNotEnded,
+  NoCandidates,
+  CampaignFinalised,
+  StorageInUse,
+  StateNotFound,
+  NoStagedCode,
+  CodeHashMismatch,
+  UpgradeNotApproved,
+  TokenNotAccepted,
+  TokenNotFound,
+  FeeTooHigh,
+  TipTooHigh,
+  MatchingTarget,
+  MatchingPoolNotFound,
+  InvalidMatchingRatio,
+  MatchingPoolEmpty,
+  RoundNotFound,
+  InvalidRoundWindow,
+  RoundNotEnded,
+  RoundFinalised,
+  SubscriptionNotFound,
+  SubscriptionTarget,
+  InvalidSubscription,
+  SubscriptionActive,
+  SubscriptionEmpty,
+  InvalidMilestones,
+  MilestonesLocked,
+  MilestoneNotFound,
+  MilestoneNotPending,
+  MilestoneNotSubmitted,
+  MilestoneNotApproved,
+  CampaignNotFinalised,
+  CampaignCancelled,
+  CampaignNotCancelled,
+  NothingToRefund,
+  MilestoneVoteClosed,
+  MilestoneVoteOpen,
+  InvalidVoting,
+  FlagNotFound,
+  FlagBond,
+  FlagResolved,
+  Frozen,
+  DedicationTooLong,
+  TokenMismatch,
+  UnknownState,
+  InvalidQuorum,
+  InvalidTokenAddress,
+  TokenAddressMismatch,
+  MatchingPoolTooSmall,
+  MatchingPoolLimit,
+  ReservedDonationId,
+  NothingToRelease,
+  MilestonesOutstanding,
+  MilestoneVoteNotFound,
+  VotePeriodTooShort,
+  NoFlagBond,
+  FlagNotResolved,
+  AlreadyVoted,
+  ZeroAmount,
+  OneYocto,
+  UpgradeGas,
+  PartnerAlreadyCandidate,
+}
+
+impl ContractError {
+  pub fn message(&self) -> &'static str {
+    match self {
+      ContractError::AccNotRegistered => ERR1_ACC_NOT_REGISTERED,
+      ContractError::InsufficientStorage => ERR2_INSUFFICIENT_STORAGE,
+      ContractError::NoStorageCanWithdraw => ERR3_NO_STORAGE_CAN_WITHDRAW,
+      ContractError::StorageWithdrawTooMuch => ERR4_STORAGE_WITHDRAW_TOO_MUCH,
+      ContractError::DepositLessThanMinStorage => ERR5_DEPOSIT_LESS_THAN_MIN_STORAGE,
+      ContractError::TransferToSelf => ERR6_TRANSFER_TO_SELF,
+      ContractError::InsufficientLpShares => ERR7_INSUFFICIENT_LP_SHARES,
+      ContractError::NotAllowed => ERR9_NOT_ALLOWED,
+      ContractError::MessageTooLong => ERR10_MESSAGE_TOO_LONG,
+      ContractError::DonationExists => ERR11_DONATION_EXISTS,
+      ContractError::DonationNotFound => ERR12_DONATION_NOT_FOUND,
+      ContractError::InvalidMsg => ERR13_INVALID_MSG,
+      ContractError::CauseNotFound => ERR14_CAUSE_NOT_FOUND,
+      ContractError::CauseArchived => ERR15_CAUSE_ARCHIVED,
+      ContractError::CauseExists => ERR16_CAUSE_EXISTS,
+      ContractError::CampaignNotInCause => ERR17_CAMPAIGN_NOT_IN_CAUSE,
+      ContractError::CampaignEnded => ERR18_CAMPAIGN_ENDED,
+      ContractError::InsufficientPool => ERR19_INSUFFICIENT_POOL,
+      ContractError::CampaignNotFound => ERR20_CAMPAIGN_NOT_FOUND,
+      ContractError::DepositNotAmount => ERR21_DEPOSIT_NOT_AMOUNT,
+      ContractError::PartnerNotFound => ERR22_PARTNER_NOT_FOUND,
+      ContractError::PartnerNotVerified => ERR23_PARTNER_NOT_VERIFIED,
+      ContractError::InsufficientTreasury => ERR24_INSUFFICIENT_TREASURY,
+      ContractError::GrantNotFound => ERR25_GRANT_NOT_FOUND,
+      ContractError::GrantNotPending => ERR26_GRANT_NOT_PENDING,
+      ContractError::AlreadyApproved => ERR27_ALREADY_APPROVED,
+      ContractError::GrantNotApproved => ERR28_GRANT_NOT_APPROVED,
+      ContractError::UnknownTarget => ERR29_UNKNOWN_TARGET,
+      ContractError::CampaignExists => ERR30_CAMPAIGN_EXISTS,
+      ContractError::EventNotFound => ERR31_EVENT_NOT_FOUND,
+      ContractError::EventExists => ERR32_EVENT_EXISTS,
+      ContractError::PartnerExists => ERR33_PARTNER_EXISTS,
+      ContractError::PartnerNotCandidate => ERR34_PARTNER_NOT_CANDIDATE,
+      ContractError::VoterNotEligible => ERR35_VOTER_NOT_ELIGIBLE,
+      ContractError::CampaignNotEnded => ERR36_CAMPAIGN_NOT_ENDED,
+      ContractError::NoCandidates => ERR37_NO_CANDIDATES,
+      ContractError::CampaignFinalised => ERR38_CAMPAIGN_FINALISED,
+      ContractError::StorageInUse => ERR39_STORAGE_IN_USE,
+      ContractError::StateNotFound => ERR40_STATE_NOT_FOUND,
+      ContractError::NoStagedCode => ERR41_NO_STAGED_CODE,
+      ContractError::CodeHashMismatch => ERR42_CODE_HASH_MISMATCH,
+      ContractError::UpgradeNotApproved => ERR43_UPGRADE_NOT_APPROVED,
+      ContractError::TokenNotAccepted => ERR44_TOKEN_NOT_ACCEPTED,
+      ContractError::TokenNotFound => ERR45_TOKEN_NOT_FOUND,
+      ContractError::FeeTooHigh => ERR46_FEE_TOO_HIGH,
+      ContractError::TipTooHigh => ERR47_TIP_TOO_HIGH,
+      ContractError::MatchingTarget => ERR48_MATCHING_TARGET,
+      ContractError::MatchingPoolNotFound => ERR49_MATCHING_POOL_NOT_FOUND,
+      ContractError::InvalidMatchingRatio => ERR50_INVALID_MATCHING_RATIO,
+      ContractError::MatchingPoolEmpty => ERR51_MATCHING_POOL_EMPTY,
+      ContractError::RoundNotFound => ERR52_ROUND_NOT_FOUND,
+      ContractError::InvalidRoundWindow => ERR53_INVALID_ROUND_WINDOW,
+      ContractError::RoundNotEnded => ERR54_ROUND_NOT_ENDED,
+      ContractError::RoundFinalised => ERR55_ROUND_FINALISED,
+      ContractError::SubscriptionNotFound => ERR56_SUBSCRIPTION_NOT_FOUND,
+      ContractError::SubscriptionTarget => ERR57_SUBSCRIPTION_TARGET,
+      ContractError::InvalidSubscription => ERR58_INVALID_SUBSCRIPTION,
+      ContractError::SubscriptionActive => ERR59_SUBSCRIPTION_ACTIVE,
+      ContractError::SubscriptionEmpty => ERR60_SUBSCRIPTION_EMPTY,
+      ContractError::InvalidMilestones => ERR61_INVALID_MILESTONES,
+      ContractError::MilestonesLocked => ERR62_MILESTONES_LOCKED,
+      ContractError::MilestoneNotFound => ERR63_MILESTONE_NOT_FOUND,
+      ContractError::MilestoneNotPending => ERR64_MILESTONE_NOT_PENDING,
+      ContractError::MilestoneNotSubmitted => ERR65_MILESTONE_NOT_SUBMITTED,
+      ContractError::MilestoneNotApproved => ERR66_MILESTONE_NOT_APPROVED,
+      ContractError::CampaignNotFinalised => ERR67_CAMPAIGN_NOT_FINALISED,
+      ContractError::CampaignCancelled => ERR68_CAMPAIGN_CANCELLED,
+      ContractError::CampaignNotCancelled => ERR69_CAMPAIGN_NOT_CANCELLED,
+      ContractError::NothingToRefund => ERR70_NOTHING_TO_REFUND,
+      ContractError::MilestoneVoteClosed => ERR71_MILESTONE_VOTE_CLOSED,
+      ContractError::MilestoneVoteOpen => ERR72_MILESTONE_VOTE_OPEN,
+      ContractError::InvalidVoting => ERR73_INVALID_VOTING,
+      ContractError::FlagNotFound => ERR74_FLAG_NOT_FOUND,
+      ContractError::FlagBond => ERR75_FLAG_BOND,
+      ContractError::FlagResolved => ERR76_FLAG_RESOLVED,
+      ContractError::Frozen => ERR77_FROZEN,
+      ContractError::DedicationTooLong => ERR78_DEDICATION_TOO_LONG,
+      ContractError::TokenMismatch => ERR79_TOKEN_MISMATCH,
+      ContractError::UnknownState => ERR80_UNKNOWN_STATE,
+      ContractError::InvalidQuorum => ERR81_INVALID_QUORUM,
+      ContractError::InvalidTokenAddress => ERR82_INVALID_TOKEN_ADDRESS,
+      ContractError::TokenAddressMismatch => ERR83_TOKEN_ADDRESS_MISMATCH,
+      ContractError::MatchingPoolTooSmall => ERR84_MATCHING_POOL_TOO_SMALL,
+      ContractError::MatchingPoolLimit => ERR85_MATCHING_POOL_LIMIT,
+      ContractError::ReservedDonationId => ERR86_RESERVED_DONATION_ID,
+      ContractError::NothingToRelease => ERR87_NOTHING_TO_RELEASE,
+      ContractError::MilestonesOutstanding => ERR88_MILESTONES_OUTSTANDING,
+      ContractError::MilestoneVoteNotFound => ERR89_MILESTONE_VOTE_NOT_FOUND,
+      ContractError::VotePeriodTooShort => ERR90_VOTE_PERIOD_TOO_SHORT,
+      ContractError::NoFlagBond => ERR91_NO_FLAG_BOND,
+      ContractError::FlagNotResolved => ERR92_FLAG_NOT_RESOLVED,
+      ContractError::AlreadyVoted => ERR93_ALREADY_VOTED,
+      ContractError::ZeroAmount => ERR94_ZERO_AMOUNT,
+      ContractError::OneYocto => ERR95_ONE_YOCTO,
+      ContractError::UpgradeGas => ERR96_UPGRADE_GAS,
+      ContractError::PartnerAlreadyCandidate => ERR97_PARTNER_ALREADY_CANDIDATE,
+    }
+  }
+
+  // Aborts the call with the coded message, reverting any state changes
+  pub fn panic(&self) -> ! {
+    env::panic_str(self.message())
+  }
+}
+
+// Result-style lookups are unwrapped with this at the contract boundary
+pub trait OrPanic<T> {
+  fn or_panic(self) -> T;
+}
+
+impl<T> OrPanic<T> for Result<T, ContractError> {
+  fn or_panic(self) -> T {
+    self.unwrap_or_else(|error| error.panic())
+  }
+}
+
+pub fn ensure(condition: bool, error: ContractError) {
+  if !condition {
+    error.panic();
+  }
+}
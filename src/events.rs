@@ -2,6 +2,9 @@ use std::collections::HashMap;
 
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 
+use crate::constants::anonymous;
+use crate::errors::{ensure, ContractError, OrPanic};
+use crate::logs::{log_event_created, log_vote_cast, RecordCreatedLog, VoteCastLog};
 use crate::*;
 
 #[derive(BorshSerialize, BorshDeserialize, Debug)]
@@ -24,7 +27,7 @@ pub struct Event {
   pub title: String,
   pub created_by: AccountId,
   pub managers: Vec<AccountId>,
-  pub cause: String, // Event course ie food security, water, tree planting, etc
+  pub cause: String, // Event course id, ie food security, water, tree planting, etc
   pub date: String,
   pub description: String,
   pub target: u128,     // Event target amount
@@ -32,7 +35,6 @@ pub struct Event {
   pub current_usd: f64, // Event current amount in usd
   pub token: String,    // The targeted token
   // pub attending: u64, // NO of people who have said they are attending
-  pub attendees: Vec<AccountId>, // Those attending
   pub venue: Option<String>,     // venue of the event
   pub event_type: String,
   pub channel: Option<String>, //Facebook, twitter spaces, youtube, google meet, etc
@@ -42,7 +44,6 @@ pub struct Event {
   pub day: i32,
   pub created_on: Timestamp,
 
-  pub voters: Vec<AccountId>, // Temporary set of people who have donated to this event, on voting, the donor is stripped from this list, can't vote again.
   pub partner: Option<String>, // The winner of the event
   pub partners: HashMap<String, u64>, // Possible companies to effect the event -> ((Kenya redcross, 20), ...) company name and votes.
   pub img: String,
@@ -78,7 +79,6 @@ impl Event {
       current: 0,
       current_usd: 0.0,
       token,
-      attendees: Vec::new(),
       venue: Some(venue),
       event_type,
       channel: Some(channel),
@@ -87,23 +87,14 @@ impl Event {
       year: <i32 as FromStr>::from_str(dates_[0].trim()).unwrap(),
       month: <i32 as FromStr>::from_str(dates_[1].trim()).unwrap(),
       day: <i32 as FromStr>::from_str(dates_[2].trim()).unwrap(),
-      voters: Vec::new(),
       partner: None,
       partners: HashMap::new(),
       img,
     }
   }
 
-  pub fn get_voter(&mut self, v: AccountId) -> bool {
-    let exists = self.voters.iter().find(|k| k == &&v);
-    if exists.is_some() {
-      self.voters.retain(|x| *x != v.clone());
-      return true;
-    }
-    false
-  }
-  pub fn add_voter(&mut self, v: AccountId) {
-    self.voters.push(v);
+  pub fn is_manager(&self, account_id: &AccountId) -> bool {
+    &self.created_by == account_id || self.managers.contains(account_id)
   }
 }
 
@@ -125,6 +116,9 @@ impl Contract {
     dates: String,
     img: String,
   ) {
+    ensure(self.events.get(&id).is_none(), ContractError::EventExists);
+    self.assert_active_cause(&cause);
+    let initial_storage = env::storage_usage();
     let event = Event::new(
       id.clone(),
       title,
@@ -142,37 +136,66 @@ impl Contract {
     );
     self.events.insert(&id.clone(), &event);
     self.events_count += 1;
+    self.charge_storage(&event.created_by, initial_storage);
+    log_event_created(RecordCreatedLog {
+      id,
+      created_by: event.created_by,
+      cause: event.cause,
+      token: event.token,
+      target: U128(event.target),
+    });
   }
 
   pub fn get_event(&self, id: String) -> Option<Event> {
     self.events.get(&id)
   }
 
-  pub fn add_event_partner(&mut self, id: String, partner: String) -> String {
-    let event = self.get_event(id.clone());
-    if event.as_ref().is_some() {
-      let mut e = event.unwrap();
-      e.partners.insert(partner.clone(), 0);
-      self.events.insert(&id.clone(), &e);
-      return "done".to_string();
+  // Registers the caller as attending, their storage deposit pays for the entry
+  pub fn attend_event(&mut self, id: String) {
+    self.find_event(&id).or_panic();
+    let attendee = env::predecessor_account_id();
+    let initial_storage = env::storage_usage();
+    self.add_event_attendee(&id, &attendee);
+    self.charge_storage(&attendee, initial_storage);
+  }
+
+  pub fn get_event_attendees(&self, id: String, page: usize, limit: usize) -> Response<AccountId> {
+    let attendees = self.event_attendee_set(&id);
+    let start_index = (page - 1) * limit;
+
+    Response {
+      results: attendees
+        .as_vector()
+        .iter()
+        .skip(start_index)
+        .take(limit)
+        .collect(),
+      count: attendees.len(),
     }
-    return "not found".to_string();
-  }
-
-  pub fn event_vote(&mut self, id: String, partner: String) -> String {
-    let v = env::predecessor_account_id();
-    let event = self.get_event(id.clone());
-    if event.as_ref().is_some() {
-      let mut e = event.unwrap();
-      if e.get_voter(v) {
-        let votes = e.partners.get_mut(&partner.clone()).unwrap();
-        *votes += 1;
-        self.events.insert(&id.clone(), &e);
-        return "done".to_string();
-      }
-      return "voter not found".to_string();
+  }
+
+  // Donors who can still vote for the event partner
+  pub fn get_event_voters(&self, id: String, page: usize, limit: usize) -> Response<AccountId> {
+    let voters = self.event_voter_set(&id);
+    let start_index = (page - 1) * limit;
+
+    Response {
+      results: voters
+        .as_vector()
+        .iter()
+        .skip(start_index)
+        .take(limit)
+        .collect(),
+      count: voters.len(),
     }
-    return "not found".to_string();
+  }
+
+  pub fn add_event_partner(&mut self, id: String, partner: String) {
+    self.internal_add_event_partner(&id, partner).or_panic()
+  }
+
+  pub fn event_vote(&mut self, id: String, partner: String) {
+    self.internal_event_vote(&id, partner).or_panic()
   }
 
   pub fn filter_events(&self, year: i32, month: i32) -> Vec<Event> {
@@ -203,3 +226,100 @@ impl Contract {
     return response;
   }
 }
+
+impl Contract {
+  pub fn find_event(&self, id: &String) -> Result<Event, ContractError> {
+    self.events.get(id).ok_or(ContractError::EventNotFound)
+  }
+
+  fn event_attendee_set(&self, id: &String) -> UnorderedSet<AccountId> {
+    self.event_attendees.get(id).unwrap_or_else(|| {
+      UnorderedSet::new(StorageKey::EventAttendeeSet {
+        event_hash: env::sha256(id.as_bytes()),
+      })
+    })
+  }
+
+  // Temporary set of people who have donated to this event, on voting, the donor is stripped from this set, can't vote again.
+  fn event_voter_set(&self, id: &String) -> UnorderedSet<AccountId> {
+    self.event_voters.get(id).unwrap_or_else(|| {
+      UnorderedSet::new(StorageKey::EventVoterSet {
+        event_hash: env::sha256(id.as_bytes()),
+      })
+    })
+  }
+
+  pub fn add_event_attendee(&mut self, id: &String, attendee: &AccountId) {
+    let mut attendees = self.event_attendee_set(id);
+    attendees.insert(attendee);
+    self.event_attendees.insert(id, &attendees);
+  }
+
+  pub fn add_event_voter(&mut self, id: &String, voter: &AccountId) {
+    let mut voters = self.event_voter_set(id);
+    voters.insert(voter);
+    self.event_voters.insert(id, &voters);
+  }
+
+  // Anonymous donors can vote too, but are kept where they can't be listed
+  pub fn add_anonymous_event_voter(&mut self, id: &str, voter: &AccountId) {
+    self
+      .event_anonymous_donors
+      .insert(&(id.to_string(), voter.clone()), &true);
+  }
+
+  // Strips the voter from the set, None if they were not in it.
+  // Some(true) when they only gave anonymously, their vote is then logged as anonymous.
+  fn take_event_voter(&mut self, id: &String, voter: &AccountId) -> Option<bool> {
+    let mut voters = self.event_voter_set(id);
+    let listed = voters.remove(voter);
+    self.event_voters.insert(id, &voters);
+    let key = (id.clone(), voter.clone());
+    let anonymous = self.event_anonymous_donors.get(&key).unwrap_or(false);
+    if anonymous {
+      self.event_anonymous_donors.insert(&key, &false);
+    }
+    (listed || anonymous).then_some(!listed)
+  }
+
+  // Managers and guardians add candidates, one already standing keeps the votes it has
+  pub fn internal_add_event_partner(
+    &mut self,
+    id: &String,
+    partner: String,
+  ) -> Result<(), ContractError> {
+    let mut e = self.find_event(id)?;
+    let caller = env::predecessor_account_id();
+    if !e.is_manager(&caller) && !self.is_guardian(&caller) {
+      return Err(ContractError::NotAllowed);
+    }
+    self.find_partner(&partner)?;
+    if e.partners.contains_key(&partner) {
+      return Err(ContractError::PartnerAlreadyCandidate);
+    }
+    e.partners.insert(partner, 0);
+    self.events.insert(id, &e);
+    Ok(())
+  }
+
+  // Each donor gets one vote for one of the candidate partners
+  fn internal_event_vote(&mut self, id: &String, partner: String) -> Result<(), ContractError> {
+    let mut e = self.find_event(id)?;
+    if !e.partners.contains_key(&partner) {
+      return Err(ContractError::PartnerNotCandidate);
+    }
+    let voter = env::predecessor_account_id();
+    let hidden = self
+      .take_event_voter(id, &voter)
+      .ok_or(ContractError::VoterNotEligible)?;
+    *e.partners.get_mut(&partner).unwrap() += 1;
+    self.events.insert(id, &e);
+    log_vote_cast(VoteCastLog {
+      kind: "event".to_string(),
+      id: id.clone(),
+      partner,
+      voter: if hidden { anonymous() } else { voter },
+    });
+    Ok(())
+  }
+}
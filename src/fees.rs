@@ -0,0 +1,99 @@
+use near_sdk::Promise;
+
+use crate::constants::MAX_FEE_BPS;
+use crate::donations::DonationTarget;
+use crate::errors::{ensure, ContractError, OrPanic};
+use crate::logs::{log_payout_sent, PayoutSentLog};
+use crate::*;
+
+#[near_bindgen]
+impl Contract {
+  // Platform fee for donations without a campaign override
+  pub fn set_fee_bps(&mut self, fee_bps: u16) {
+    self.assert_owner();
+    assert_fee_bps(fee_bps);
+    self.config.fee_bps = fee_bps;
+  }
+
+  // None goes back to the platform fee, 0 waives the fee for this campaign
+  pub fn set_campaign_fee(&mut self, id: String, fee_bps: Option<u16>) {
+    self.assert_owner();
+    if let Some(fee_bps) = fee_bps {
+      assert_fee_bps(fee_bps);
+    }
+    let mut campaign = self.find_campaign(&id).or_panic();
+    campaign.fee_bps = fee_bps;
+    self.campaigns.insert(&id, &campaign);
+    self.log_campaign_change(id, "fee");
+  }
+
+  // Withdraws collected fees to the owner, everything collected in the token when no amount is given
+  pub fn withdraw_fees(&mut self, token: String, amount: Option<U128>) -> Promise {
+    self.assert_owner();
+    let collected = self.fee_treasury.get(&token).unwrap_or(0);
+    let amount = amount.map_or(collected, |amount| amount.0);
+    ensure(
+      amount > 0 && amount <= collected,
+      ContractError::InsufficientTreasury,
+    );
+    self.fee_treasury.insert(&token, &(collected - amount));
+
+    self
+      .transfer_funds(&token, self.owner.clone(), amount)
+      .then(Self::ext(env::current_account_id()).on_fees_withdrawn(token, U128(amount)))
+  }
+
+  #[private]
+  pub fn on_fees_withdrawn(&mut self, token: String, amount: U128) -> bool {
+    if !Self::payout_succeeded() {
+      self.credit_fees(&token, amount.0);
+      return false;
+    }
+    log_payout_sent(PayoutSentLog {
+      memo: format!("fees:{}", token),
+      token,
+      receiver_id: self.owner.clone(),
+      amount,
+    });
+    true
+  }
+
+  pub fn get_fee_treasury(&self) -> Vec<(String, U128)> {
+    self
+      .fee_treasury
+      .iter()
+      .map(|(token, amount)| (token, U128(amount)))
+      .collect()
+  }
+}
+
+impl Contract {
+  // Campaign override first, then the platform fee
+  pub fn fee_bps_for(&self, target: &DonationTarget) -> u16 {
+    match target {
+      DonationTarget::Campaign { id } => self
+        .campaigns
+        .get(id)
+        .and_then(|campaign| campaign.fee_bps)
+        .unwrap_or(self.config.fee_bps),
+      _ => self.config.fee_bps,
+    }
+  }
+
+  pub fn credit_fees(&mut self, token: &String, amount: u128) {
+    if amount == 0 {
+      return;
+    }
+    let collected = self.fee_treasury.get(token).unwrap_or(0);
+    self.fee_treasury.insert(token, &(collected + amount));
+  }
+}
+
+pub fn assert_fee_bps(fee_bps: u16) {
+  ensure(fee_bps <= MAX_FEE_BPS, ContractError::FeeTooHigh);
+}
+
+// Fee on an amount, rounded down so donors are never charged more than the rate
+pub fn fee_amount(amount: u128, fee_bps: u16) -> u128 {
+  amount * u128::from(fee_bps) / u128::from(MAX_FEE_BPS)
+}
@@ -0,0 +1,233 @@
+use near_sdk::Promise;
+
+use crate::constants::{FLAG_BOND, NEAR_TOKEN};
+use crate::errors::{ensure, ContractError, OrPanic};
+use crate::logs::{log_payout_sent, PayoutSentLog};
+use crate::*;
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum FlagTarget {
+  Campaign { id: String },
+  Event { id: String },
+  Partner { id: String },
+}
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "lowercase")]
+pub enum FlagStatus {
+  Open,      // Waiting for a guardian
+  Frozen,    // Under review, the target takes no donations and pays nothing out
+  Dismissed, // Nothing wrong found
+  Upheld,    // Campaigns are cancelled and refunded, partners lose their verification
+}
+
+// A report of a suspicious campaign, event or partner
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Flag {
+  pub id: u64,
+  pub reporter: AccountId,
+  pub target: FlagTarget,
+  pub reason: String,
+  pub evidence: String, // Link to what backs the report
+  pub bond: U128,       // Returned unless the flag is dismissed as spam, 0 once paid out
+  pub status: FlagStatus,
+  pub reviewed_by: Option<AccountId>,
+  pub created_on: Timestamp,
+  pub resolved_on: Option<Timestamp>,
+}
+
+#[near_bindgen]
+impl Contract {
+  // Anyone can flag with the bond attached, their storage deposit pays for the record
+  #[payable]
+  pub fn flag(&mut self, target: FlagTarget, reason: String, evidence: String) -> u64 {
+    ensure(
+      env::attached_deposit() == FLAG_BOND,
+      ContractError::FlagBond,
+    );
+    match &target {
+      FlagTarget::Campaign { id } => {
+        self.find_campaign(id).or_panic();
+      }
+      FlagTarget::Event { id } => {
+        self.find_event(id).or_panic();
+      }
+      FlagTarget::Partner { id } => {
+        self.find_partner(id).or_panic();
+      }
+    }
+    let reporter = env::predecessor_account_id();
+    let initial_storage = env::storage_usage();
+
+    let flag = Flag {
+      id: self.flags_count,
+      reporter: reporter.clone(),
+      target,
+      reason,
+      evidence,
+      bond: U128(FLAG_BOND),
+      status: FlagStatus::Open,
+      reviewed_by: None,
+      created_on: env::block_timestamp(),
+      resolved_on: None,
+    };
+    self.flags.insert(&flag.id, &flag);
+    self.flags_count += 1;
+    self.charge_storage(&reporter, initial_storage);
+    flag.id
+  }
+
+  // Guardians freeze the target while they look into the flag, a target frozen under another flag stays with it
+  pub fn freeze_flagged(&mut self, id: u64) {
+    self.assert_guardian();
+    let mut flag = self.find_flag(id).or_panic();
+    ensure(flag.status == FlagStatus::Open, ContractError::FlagResolved);
+    ensure(
+      !self.frozen.contains_key(&flag.target),
+      ContractError::Frozen,
+    );
+    flag.status = FlagStatus::Frozen;
+    flag.reviewed_by = Some(env::predecessor_account_id());
+    self.frozen.insert(&flag.target, &id);
+    self.flags.insert(&id, &flag);
+    if let FlagTarget::Campaign { id: campaign } = &flag.target {
+      self.log_campaign_change(campaign.clone(), "frozen");
+    }
+  }
+
+  // Unfounded flags get their bond back, spam flags leave it in the general treasury
+  pub fn dismiss_flag(&mut self, id: u64, spam: bool) {
+    let mut flag = self.resolve_flag(id, FlagStatus::Dismissed);
+    if spam {
+      self.credit_treasury(&NEAR_TOKEN.to_string(), flag.bond.0, 0.0);
+      flag.bond = U128(0);
+      self.flags.insert(&id, &flag);
+    } else {
+      self.return_flag_bond(flag);
+    }
+  }
+
+  // Cancels a flagged campaign so its donors can claim refunds, a flagged partner loses its
+  // verification and a flagged event stays frozen
+  pub fn uphold_flag(&mut self, id: u64) {
+    let flag = self.resolve_flag(id, FlagStatus::Upheld);
+    match flag.target.clone() {
+      FlagTarget::Campaign { id: campaign } => {
+        if !self.is_campaign_cancelled(&campaign) {
+          self.internal_cancel_campaign(&campaign);
+        }
+      }
+      // The event stays frozen for good, so a flag still under review can't unfreeze it either
+      FlagTarget::Event { .. } => {
+        let upheld = self
+          .frozen
+          .get(&flag.target)
+          .and_then(|owner| self.flags.get(&owner))
+          .is_some_and(|owner| owner.status == FlagStatus::Upheld);
+        if !upheld {
+          self.frozen.insert(&flag.target, &id);
+        }
+      }
+      FlagTarget::Partner { id: partner } => {
+        self.verify_partner(partner, false);
+      }
+    }
+    self.return_flag_bond(flag);
+  }
+
+  // Reporters claim a bond whose return failed
+  pub fn claim_flag_bond(&mut self, id: u64) -> Promise {
+    let flag = self.find_flag(id).or_panic();
+    ensure(
+      flag.reporter == env::predecessor_account_id(),
+      ContractError::NotAllowed,
+    );
+    ensure(
+      flag.status == FlagStatus::Dismissed || flag.status == FlagStatus::Upheld,
+      ContractError::FlagNotResolved,
+    );
+    ensure(flag.bond.0 > 0, ContractError::NoFlagBond);
+    self.return_flag_bond(flag)
+  }
+
+  #[private]
+  pub fn on_flag_bond_returned(&mut self, id: u64, amount: U128) -> bool {
+    let mut flag = self.find_flag(id).or_panic();
+    if !Self::payout_succeeded() {
+      flag.bond = amount;
+      self.flags.insert(&id, &flag);
+      return false;
+    }
+    log_payout_sent(PayoutSentLog {
+      token: NEAR_TOKEN.to_string(),
+      receiver_id: flag.reporter,
+      amount,
+      memo: format!("flag:{}", id),
+    });
+    true
+  }
+
+  pub fn get_flag(&self, id: u64) -> Option<Flag> {
+    self.flags.get(&id)
+  }
+
+  pub fn get_flags(&self, page: usize, limit: usize) -> Response<Flag> {
+    let start_index = (page - 1) * limit;
+
+    let flags: Vec<Flag> = self.flags.values().skip(start_index).take(limit).collect();
+
+    Response {
+      results: flags,
+      count: self.flags.len(),
+    }
+  }
+
+  pub fn is_frozen(&self, target: FlagTarget) -> bool {
+    self.frozen.contains_key(&target)
+  }
+}
+
+impl Contract {
+  pub fn find_flag(&self, id: u64) -> Result<Flag, ContractError> {
+    self.flags.get(&id).ok_or(ContractError::FlagNotFound)
+  }
+
+  pub fn assert_not_frozen(&self, target: FlagTarget) {
+    ensure(!self.frozen.contains_key(&target), ContractError::Frozen);
+  }
+
+  // The bond is kept at 0 while the payout is in flight, a failed payout puts it back for claim_flag_bond
+  fn return_flag_bond(&mut self, mut flag: Flag) -> Promise {
+    let amount = flag.bond;
+    flag.bond = U128(0);
+    self.flags.insert(&flag.id, &flag);
+    self
+      .transfer_funds(&NEAR_TOKEN.to_string(), flag.reporter, amount.0)
+      .then(Self::ext(env::current_account_id()).on_flag_bond_returned(flag.id, amount))
+  }
+
+  // Closes an open or frozen flag, the target is unfrozen if this flag froze it
+  fn resolve_flag(&mut self, id: u64, status: FlagStatus) -> Flag {
+    self.assert_guardian();
+    let mut flag = self.find_flag(id).or_panic();
+    ensure(
+      flag.status == FlagStatus::Open || flag.status == FlagStatus::Frozen,
+      ContractError::FlagResolved,
+    );
+    if self.frozen.get(&flag.target) == Some(id) {
+      self.frozen.remove(&flag.target);
+      if let FlagTarget::Campaign { id: campaign } = &flag.target {
+        self.log_campaign_change(campaign.clone(), "unfrozen");
+      }
+    }
+    flag.status = status;
+    flag.reviewed_by = Some(env::predecessor_account_id());
+    flag.resolved_on = Some(env::block_timestamp());
+    self.flags.insert(&id, &flag);
+    flag
+  }
+}
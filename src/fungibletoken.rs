@@ -1,86 +1,108 @@
 use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+use near_contract_standards::storage_management::StorageBalance;
 use near_sdk::json_types::U128;
-use near_sdk::serde_json::json;
-use near_sdk::{ext_contract, near_bindgen, Promise, PromiseOrValue, PromiseResult, ONE_YOCTO};
+use near_sdk::{near_bindgen, Promise, PromiseOrValue, PromiseResult, ONE_YOCTO};
 
-use crate::constants::{GAS_FOR_BASIC_OP, GAS_FOR_FT_TRANSFER};
-use crate::errors::ERR9_NOT_ALLOWED;
+use crate::constants::{
+  getAccountId, FT_STORAGE_DEPOSIT, GAS_FOR_BASIC_OP, GAS_FOR_FT_STORAGE, GAS_FOR_FT_TRANSFER,
+  GAS_FOR_ON_PAYOUT_REGISTERED, GAS_FOR_ON_PAYOUT_STORAGE_CHECKED, NEAR_TOKEN,
+};
+use crate::donations::{check_donation_id, Dedication, DonationInput, DonationTarget};
+use crate::errors::{ensure, ContractError, OrPanic};
+use crate::matching::MatchingPoolMsg;
+use crate::subscriptions::SubscriptionMsg;
+use crate::tokens::ext_ft;
 use crate::*;
 
-// #[near_bindgen]
-#[ext_contract(ext_self)]
-trait ContractCallBacks {
-  fn deposit_tokens(
-    &mut self,
-    account_id: AccountId,
-    token_id: AccountId,
-    amount: U128,
-    msg: String,
-  ) -> PromiseOrValue<U128>;
+// Donation details sent as the ft_transfer_call msg
+#[derive(Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DonationMsg {
+  pub id: String,
+  pub target: DonationTarget,
+  pub amount_usd: f64,   // Value of the donation without the tip
+  pub tip: Option<U128>, // Part of the transferred amount that goes to the platform
+  pub anonymous: Option<bool>,
+  pub message: Option<String>,
+  pub dedication: Option<Dedication>,
+}
 
-  fn withdraw_tokens(
-    &mut self,
-    account_id: AccountId,
-    token_id: AccountId,
-    amount: U128,
-    target: String, // general, event, campaign
-    id: String,
-  ) -> PromiseOrValue<U128>;
+impl DonationMsg {
+  pub fn parse(msg: &str) -> Self {
+    if msg.trim_start().starts_with('{') {
+      return near_sdk::serde_json::from_str(msg)
+        .map_err(|_| ContractError::InvalidMsg)
+        .or_panic();
+    }
+
+    // Legacy format donation_id:target:campaign_id:event_id:amount_usd[:anonymous]
+    let details = msg.split(":").collect::<Vec<&str>>();
+    ensure(details.len() >= 5, ContractError::InvalidMsg);
+    let target = DonationTarget::from_legacy(
+      details[1],
+      Some(details[2].to_string()),
+      Some(details[3].to_string()),
+      None,
+    );
+    Self {
+      id: details[0].to_string(),
+      target: target.ok_or(ContractError::UnknownTarget).or_panic(),
+      amount_usd: details[4]
+        .parse::<f64>()
+        .map_err(|_| ContractError::InvalidMsg)
+        .or_panic(),
+      tip: None,
+      anonymous: Some(details.get(5) == Some(&"anonymous")),
+      message: None,
+      dedication: None,
+    }
+  }
 }
 
 #[near_bindgen]
-impl ContractCallBacks for Contract {
-  fn deposit_tokens(
+impl Contract {
+  // Only scheduled by ft_on_transfer, calling it directly would credit tokens that were never sent
+  #[private]
+  pub fn deposit_tokens(
     &mut self,
     donor: AccountId,
     token: AccountId,
     amount: U128,
-    msg: String, // msg to contain donation_id, target, campaign_id, event_id, amount_usd
+    msg: String, // JSON DonationMsg, MatchingPoolMsg or SubscriptionMsg, or the legacy donation_id:target:campaign_id:event_id:amount_usd[:anonymous]
   ) -> PromiseOrValue<U128> {
-    let details_split = msg.split(":");
-    let deposit_details_vec = details_split.collect::<Vec<&str>>();
-
-    let donation_id = deposit_details_vec[0].to_string(); // Donation Id
-    let target = deposit_details_vec[1].to_string(); // target which can either be general, event or campaign
-    let cid = deposit_details_vec[2].to_string(); // Campaign Id
-    let eid = deposit_details_vec[3].to_string(); // Event Id
-    let amt_usd = deposit_details_vec[4].to_string(); // Amount in USD
+    // Sponsors fund matching pools with the same transfer
+    if let Ok(pool) = near_sdk::serde_json::from_str::<MatchingPoolMsg>(&msg) {
+      self.insert_matching_pool(donor, token.to_string(), amount.0, pool.matching_pool);
+      return PromiseOrValue::Value(U128(0));
+    }
+    // Donors pre-fund subscriptions the same way
+    if let Ok(subscription) = near_sdk::serde_json::from_str::<SubscriptionMsg>(&msg) {
+      self.insert_subscription(
+        donor,
+        token.to_string(),
+        amount.0,
+        subscription.subscription,
+      );
+      return PromiseOrValue::Value(U128(0));
+    }
+    let details = DonationMsg::parse(&msg);
+    check_donation_id(&details.id).or_panic();
+    let tip = details.tip.map_or(0, |tip| tip.0);
+    ensure(tip < amount.0, ContractError::TipTooHigh);
 
-    self.create_donation(
-      donation_id,
+    self.create_donation(DonationInput {
+      id: details.id,
       donor,
-      token.to_string(),
-      amount,
-      amt_usd.parse::<f64>().unwrap(),
-      target,
-      eid,
-      cid,
-    );
-
-    PromiseOrValue::Value(U128(0))
-  }
-
-  fn withdraw_tokens(
-    &mut self,
-    account_id: AccountId,
-    token_id: AccountId,
-    amount: U128,
-    target: String,
-    id: String,
-  ) -> PromiseOrValue<U128> {
-    assert!(
-      env::predecessor_account_id() == env::current_account_id(),
-      "{}",
-      ERR9_NOT_ALLOWED
-    );
+      token: token.to_string(),
+      amount: U128(amount.0 - tip),
+      tip,
+      amount_usd: details.amount_usd,
+      target: details.target,
+      anonymous: details.anonymous.unwrap_or(false),
+      message: details.message,
+      dedication: details.dedication,
+    });
 
-    match env::promise_result(0) {
-      PromiseResult::NotReady => unreachable!(),
-      PromiseResult::Successful(_) => {
-        // Todo
-      }
-      PromiseResult::Failed => {}
-    }
     PromiseOrValue::Value(U128(0))
   }
 }
@@ -94,9 +116,11 @@ impl FungibleTokenReceiver for Contract {
     &mut self,
     sender_id: AccountId,
     amount: U128,
-    msg: String, // msg to contain donation_id, event_type, campaign_id, event_id, amount_usd
+    msg: String, // see DonationMsg
   ) -> PromiseOrValue<U128> {
     let token_id = env::predecessor_account_id();
+    // Panicking makes the token contract refund the whole transfer
+    self.assert_token_accepted(&token_id);
     near_sdk::PromiseOrValue::Promise(
       Self::ext(env::current_account_id()).deposit_tokens(sender_id, token_id, amount, msg),
     )
@@ -105,58 +129,140 @@ impl FungibleTokenReceiver for Contract {
 
 #[near_bindgen]
 impl Contract {
+  // Token payout, stage 2: registers the receiver with the token first if it has no storage there
   #[private]
-  pub fn send_tokens(
+  pub fn on_payout_storage_checked(
     &mut self,
-    from: AccountId,
-    to: AccountId,
     token: AccountId,
+    receiver_id: AccountId,
     amount: U128,
-    chat_id: String,
-  ) -> Promise {
-    let cross_contract_call = Promise::new(token.clone()).function_call(
-      "ft_transfer".to_string(),
-      json!({ "receiver_id": to, "amount":  amount.clone()})
-        .to_string()
-        .into_bytes(),
-      ONE_YOCTO,
-      GAS_FOR_FT_TRANSFER,
-    );
-
-    let callback = Promise::new(env::current_account_id()).function_call(
-      "withdraw_tokens".to_string(),
-      json!({ "account_id": from,"token_id": token.clone(), "amount":  amount, "chat_id": chat_id})
-        .to_string()
-        .into_bytes(),
-      0,
-      GAS_FOR_BASIC_OP,
-    );
+  ) -> PromiseOrValue<bool> {
+    let storage = match env::promise_result(0) {
+      PromiseResult::Successful(result) => {
+        near_sdk::serde_json::from_slice::<Option<StorageBalance>>(&result).ok()
+      }
+      _ => None,
+    };
+    match storage {
+      Some(Some(_)) => PromiseOrValue::Promise(self.ft_payout(token, receiver_id, amount)),
+      Some(None) => {
+        if self.ft_storage_budget < FT_STORAGE_DEPOSIT {
+          return PromiseOrValue::Value(false);
+        }
+        self.ft_storage_budget -= FT_STORAGE_DEPOSIT;
+        // Asked again right before the deposit, an account registered in between gets all of it back
+        PromiseOrValue::Promise(
+          ext_ft::ext(token.clone())
+            .with_static_gas(GAS_FOR_FT_STORAGE)
+            .storage_balance_of(receiver_id.clone())
+            .and(
+              ext_ft::ext(token.clone())
+                .with_attached_deposit(FT_STORAGE_DEPOSIT)
+                .with_static_gas(GAS_FOR_FT_STORAGE)
+                .storage_deposit(Some(receiver_id.clone()), Some(true)),
+            )
+            .then(
+              Self::ext(env::current_account_id())
+                .with_static_gas(GAS_FOR_ON_PAYOUT_REGISTERED)
+                .on_payout_registered(token, receiver_id, amount),
+            ),
+        )
+      }
+      // The token could not tell, nothing was spent yet
+      None => PromiseOrValue::Value(false),
+    }
+  }
 
-    cross_contract_call.then(callback)
+  // Token payout, stage 3: sends the tokens once the receiver is registered
+  #[private]
+  pub fn on_payout_registered(
+    &mut self,
+    token: AccountId,
+    receiver_id: AccountId,
+    amount: U128,
+  ) -> PromiseOrValue<bool> {
+    let registered = match env::promise_result(0) {
+      PromiseResult::Successful(result) => {
+        near_sdk::serde_json::from_slice::<Option<StorageBalance>>(&result)
+          .ok()
+          .map(|storage| storage.is_some())
+      }
+      _ => None,
+    };
+    match env::promise_result(1) {
+      PromiseResult::Successful(result) => {
+        // registration_only refunds what the token doesn't need, that part goes back to the budget
+        let balance = near_sdk::serde_json::from_slice::<StorageBalance>(&result);
+        self.ft_storage_budget += match (registered, balance) {
+          (Some(true), _) => FT_STORAGE_DEPOSIT,
+          (Some(false), Ok(balance)) => FT_STORAGE_DEPOSIT.saturating_sub(balance.total.0),
+          // Whatever came back can't be told apart, it stays out of the budget
+          _ => 0,
+        };
+        PromiseOrValue::Promise(self.ft_payout(token, receiver_id, amount))
+      }
+      _ => {
+        // The deposit came back with the failed call
+        self.ft_storage_budget += FT_STORAGE_DEPOSIT;
+        PromiseOrValue::Value(false)
+      }
+    }
   }
 
+  // Token payout, stage 4: a failed ft_transfer leaves the tokens with the contract
   #[private]
-  pub fn withdraw_named_asset(&mut self, token: AccountId, amount: U128) -> Promise {
-    let me = env::predecessor_account_id();
-
-    let cross_contract_call = Promise::new(token.clone()).function_call(
-      "ft_transfer".to_string(),
-      json!({ "receiver_id": me.clone(), "amount":  amount.clone()})
-        .to_string()
-        .into_bytes(),
-      ONE_YOCTO,
-      GAS_FOR_FT_TRANSFER,
-    );
+  pub fn on_payout_transferred(&mut self) -> bool {
+    matches!(env::promise_result(0), PromiseResult::Successful(_))
+  }
 
-    let callback = Promise::new(env::current_account_id()).function_call(
-      "withdraw_asset".to_string(),
-      json!({ "acc": me,"token": token.clone(), "amount":  amount})
-        .to_string()
-        .into_bytes(),
-      0,
-      GAS_FOR_BASIC_OP,
-    );
+  // Part of the contract balance that may be spent registering payout receivers
+  pub fn set_ft_storage_budget(&mut self, amount: U128) {
+    self.assert_owner();
+    self.ft_storage_budget = amount.0;
+  }
+
+  pub fn get_ft_storage_budget(&self) -> U128 {
+    U128(self.ft_storage_budget)
+  }
+}
+
+impl Contract {
+  // Sends NEAR or a fungible token held by the contract, check the outcome with payout_succeeded.
+  // Token payouts go storage_balance_of -> storage_deposit if unregistered -> ft_transfer.
+  pub fn transfer_funds(&self, token: &String, receiver_id: AccountId, amount: u128) -> Promise {
+    if token == NEAR_TOKEN {
+      return Promise::new(receiver_id).transfer(amount);
+    }
+    let token = getAccountId(token.clone());
+    ext_ft::ext(token.clone())
+      .with_static_gas(GAS_FOR_FT_STORAGE)
+      .storage_balance_of(receiver_id.clone())
+      .then(
+        Self::ext(env::current_account_id())
+          .with_static_gas(GAS_FOR_ON_PAYOUT_STORAGE_CHECKED)
+          .on_payout_storage_checked(token, receiver_id, U128(amount)),
+      )
+  }
+
+  fn ft_payout(&self, token: AccountId, receiver_id: AccountId, amount: U128) -> Promise {
+    ext_ft::ext(token)
+      .with_attached_deposit(ONE_YOCTO)
+      .with_static_gas(GAS_FOR_FT_TRANSFER)
+      .ft_transfer(receiver_id, amount, None)
+      .then(
+        Self::ext(env::current_account_id())
+          .with_static_gas(GAS_FOR_BASIC_OP)
+          .on_payout_transferred(),
+      )
+  }
 
-    cross_contract_call.then(callback)
+  // NEAR transfers return nothing, token payouts return whether every stage went through
+  pub fn payout_succeeded() -> bool {
+    match env::promise_result(0) {
+      PromiseResult::Successful(result) => {
+        result.is_empty() || near_sdk::serde_json::from_slice::<bool>(&result).unwrap_or(false)
+      }
+      _ => false,
+    }
   }
 }
@@ -0,0 +1,60 @@
+use crate::errors::{ensure, ContractError};
+use crate::*;
+
+#[near_bindgen]
+impl Contract {
+  pub fn add_guardian(&mut self, account_id: AccountId) {
+    self.assert_owner();
+    self.gurdians.insert(&account_id);
+  }
+
+  pub fn remove_guardian(&mut self, account_id: AccountId) {
+    self.assert_owner();
+    self.gurdians.remove(&account_id);
+  }
+
+  pub fn set_owner(&mut self, owner: AccountId) {
+    self.assert_owner();
+    self.owner = owner;
+  }
+
+  pub fn get_owner(&self) -> AccountId {
+    self.owner.clone()
+  }
+
+  pub fn get_config(&self) -> Config {
+    self.config.clone()
+  }
+
+  pub fn get_guardians(&self) -> Vec<AccountId> {
+    self.gurdians.to_vec()
+  }
+}
+
+impl Contract {
+  pub fn is_guardian(&self, account_id: &AccountId) -> bool {
+    self.gurdians.contains(account_id)
+  }
+
+  pub fn assert_owner(&self) {
+    ensure(
+      env::predecessor_account_id() == self.owner,
+      ContractError::NotAllowed,
+    );
+  }
+
+  pub fn assert_guardian(&self) {
+    ensure(
+      self.is_guardian(&env::predecessor_account_id()),
+      ContractError::NotAllowed,
+    );
+  }
+}
+
+// Grant and upgrade quorums, a quorum of 0 would need no guardian approval at all
+pub fn check_quorum(quorum: u64) -> Result<(), ContractError> {
+  if quorum == 0 {
+    return Err(ContractError::InvalidQuorum);
+  }
+  Ok(())
+}
@@ -1,32 +1,64 @@
-use campaign::Campaign;
+use account::Account;
+use campaign::{Campaign, PartnerVoting};
+use causepools::{CausePool, PoolAllocation, PoolBalance};
 use causes::Cause;
-use donations::Donation;
+use donations::{Donation, DonationTarget};
 use events::Event;
+use flags::{Flag, FlagTarget};
+use matching::MatchingPool;
+use migration::DonationV0;
+use milestones::CampaignFunds;
+use milestonevotes::{MilestoneVote, MilestoneVoting};
 use partners::Partner;
+use rounds::{Round, RoundTally};
+use stats::{PeriodTotals, TokenTotals};
+use subscriptions::Subscription;
+use tokens::AcceptedToken;
+use treasury::GrantProposal;
+use upgrade::StagedUpgrade;
 
 use near_sdk::{
   borsh::{self, BorshDeserialize, BorshSerialize},
-  collections::{UnorderedMap, UnorderedSet},
+  collections::{LazyOption, LookupMap, LookupSet, UnorderedMap, UnorderedSet},
   env,
   json_types::U128,
   near_bindgen,
   serde::{Deserialize, Serialize},
-  AccountId, Timestamp,
+  AccountId, Balance, BorshStorageKey, PanicOnDefault, Timestamp,
 };
 
+use constants::STATE_VERSION;
+use errors::OrPanic;
 use std::str::FromStr;
 
 pub mod account;
 pub mod campaign;
+pub mod causepools;
 pub mod causes;
 pub mod constants;
 pub mod donations;
 pub mod errors;
 pub mod events;
+pub mod fees;
+pub mod flags;
 pub mod fungibletoken;
+pub mod guardians;
+pub mod logs;
+pub mod matching;
+pub mod migration;
+pub mod milestones;
+pub mod milestonevotes;
 pub mod partners;
+pub mod rounds;
+pub mod stats;
+pub mod subscriptions;
+mod tests;
+pub mod tokens;
+pub mod treasury;
+pub mod upgrade;
+pub mod utils;
 
-#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug)]
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone)]
 #[serde(crate = "near_sdk::serde")]
 pub struct TokenMetadata {
   pub address: String,
@@ -52,21 +84,125 @@ pub struct ContractStats {
   pub donations: u64,
   pub tokens: u64,
   pub partners: u64,
+  pub donors: u64,
+  pub anonymous_donations: u64,
   pub total_usd: f64,
+  pub tips: u64, // Donations that came with a tip
+  pub tips_usd: f64,
+}
+
+// Prefixes of every collection, the borsh discriminant is the prefix so only ever append variants.
+// Nested collections owned by a single record carry the sha256 of the record id.
+#[derive(BorshStorageKey, BorshSerialize)]
+pub enum StorageKey {
+  Guardians,
+  Causes,
+  Events,
+  Campaigns,
+  Donations,
+  Tokens,
+  Partners,
+  CausePools,
+  PoolAllocations,
+  Treasury,
+  Grants,
+  Donors,
+  TokenStats,
+  CauseStats,
+  MonthlyStats,
+  Accounts,
+  CampaignVoters,
+  CampaignVoterSet { campaign_hash: Vec<u8> },
+  EventVoters,
+  EventVoterSet { event_hash: Vec<u8> },
+  EventAttendees,
+  EventAttendeeSet { event_hash: Vec<u8> },
+  StagedCode,
+  AcceptedTokens,
+  FeeTreasury,
+  MatchingPools,
+  Rounds,
+  RoundTallies,
+  RoundContributions,
+  Subscriptions,
+  CampaignFunds,
+  CampaignContributions,
+  MilestoneVoting,
+  MilestoneVotes,
+  MilestoneVoters,
+  Flags,
+  Frozen,
+  CampaignAnonymousDonors,
+  EventAnonymousDonors,
+  TargetMatchingPools,
+  ActiveSubscriptions,
+  CampaignVoted,
+}
+
+// Settings chosen at deployment, owner can change them later
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Config {
+  pub fee_bps: u16,                 // Platform fee on donations in basis points, 0 for none
+  pub grant_quorum: u64,            // Guardian approvals needed to execute a grant
+  pub upgrade_quorum: u64,          // Guardian approvals needed to upgrade
+  pub milestone_quorum_bps: u16,    // Share of a campaign's donated weight that has to vote on a milestone
+  pub milestone_threshold_bps: u16, // Share of the votes cast that has to approve it
+  pub milestone_vote_period: u64,   // Nanoseconds donors have to vote on milestone evidence
+  pub partner_voting: PartnerVoting, // How donor votes for a campaign partner are counted
 }
 
 #[near_bindgen]
-#[derive(BorshSerialize, BorshDeserialize, Debug)]
+#[derive(BorshSerialize, BorshDeserialize, Debug, PanicOnDefault)]
 pub struct Contract {
+  pub owner: AccountId, // Manages guardians and configuration
+  pub config: Config,
   pub gurdians: UnorderedSet<AccountId>,
   pub running: bool,
-  pub causes: UnorderedSet<Cause>, // causes TRee planting
+  pub causes: UnorderedMap<String, Cause>, // causes TRee planting
   pub events: UnorderedMap<String, Event>,
   pub campaigns: UnorderedMap<String, Campaign>,
-  pub donations: UnorderedSet<Donation>,
-  pub tokens: UnorderedMap<String, TokenMetadata>,
+  pub donations: UnorderedMap<String, Donation>,
+  pub tokens: UnorderedMap<AccountId, AcceptedToken>, // Fungible tokens accepted for donations
   pub partners: UnorderedMap<String, Partner>,
   pub total_usd: f64,
+  pub total_tips_usd: f64, // Tips are not part of total_usd
+  pub cause_pools: UnorderedMap<String, CausePool>, // Funds donated to a cause, waiting to be allocated
+  pub pool_allocations: UnorderedMap<u64, PoolAllocation>,
+  pub treasury: UnorderedMap<String, PoolBalance>, // General donations per token
+  pub fee_treasury: UnorderedMap<String, u128>,    // Platform fees per token, withdrawn by the owner
+  pub grants: UnorderedMap<u64, GrantProposal>,    // Proposals to spend the treasury
+  pub matching_pools: UnorderedMap<u64, MatchingPool>, // Sponsor funds matching donations
+  pub target_matching_pools: LookupMap<DonationTarget, Vec<u64>>, // Pools with a balance, per target
+  pub rounds: UnorderedMap<u64, Round>, // Quadratic funding rounds
+  pub round_tallies: LookupMap<(u64, String), RoundTally>, // (round, campaign)
+  pub round_contributions: LookupMap<(u64, String, AccountId), u128>, // (round, campaign, donor)
+  pub open_rounds: Vec<u64>, // Rounds donations are counted for, until they are finalised
+  pub subscriptions: UnorderedMap<u64, Subscription>, // Pre-funded recurring donations
+  pub active_subscriptions: UnorderedSet<u64>, // Subscriptions keepers still have to look at
+  pub subscription_cursor: u64, // Where the next keeper call starts in active_subscriptions
+  pub campaign_funds: LookupMap<String, CampaignFunds>, // Milestones, releases and refunds per campaign
+  pub campaign_contributions: LookupMap<(String, AccountId), u128>, // (campaign, donor), net donated
+  pub milestone_voting: LookupMap<String, MilestoneVoting>, // Campaigns not using the config defaults
+  pub milestone_votes: LookupMap<(String, u64), MilestoneVote>, // (campaign, milestone)
+  pub milestone_voters: LookupMap<(String, u64, AccountId), Timestamp>, // When the vote the donor voted in opened
+  pub flags: UnorderedMap<u64, Flag>, // Reports of suspicious campaigns, events and partners
+  pub frozen: LookupMap<FlagTarget, u64>, // Frozen targets, by the flag that froze them
+
+  pub donors: LookupSet<AccountId>, // Unique donors
+  pub token_stats: UnorderedMap<String, TokenTotals>, // Totals per token
+  pub cause_stats: UnorderedMap<String, PeriodTotals>, // Totals per cause id
+  pub monthly_stats: UnorderedMap<String, PeriodTotals>, // Totals per month, keyed YYYY-MM
+
+  pub accounts: LookupMap<AccountId, Account>, // Storage deposits
+
+  pub campaign_voters: LookupMap<String, UnorderedSet<AccountId>>, // Donors yet to vote, per campaign
+  pub event_voters: LookupMap<String, UnorderedSet<AccountId>>,    // Donors yet to vote, per event
+  pub event_attendees: LookupMap<String, UnorderedSet<AccountId>>,
+  // Donors who gave anonymously, kept out of the voter sets. True while they can still vote.
+  pub campaign_anonymous_donors: LookupMap<(String, AccountId), bool>, // (campaign, donor)
+  pub event_anonymous_donors: LookupMap<(String, AccountId), bool>,    // (event, donor)
+  pub campaign_voted: LookupSet<(String, AccountId)>, // (campaign, donor), donating again doesn't give another vote
 
   pub causes_count: u64,
   pub events_count: u64,
@@ -74,45 +210,88 @@ pub struct Contract {
   pub donations_count: u64,
   pub tokens_count: u64,
   pub partners_count: u64,
-}
+  pub pool_allocations_count: u64,
+  pub grants_count: u64,
+  pub matching_pools_count: u64,
+  pub rounds_count: u64,
+  pub subscriptions_count: u64,
+  pub flags_count: u64,
+  pub donors_count: u64,
+  pub anonymous_donations_count: u64,
+  pub tips_count: u64,
 
-impl Default for Contract {
-  fn default() -> Self {
-    Self {
-      gurdians: UnorderedSet::new(b"g"),
-      running: true,
-      causes: UnorderedSet::new(b"c"),
-      events: UnorderedMap::new(b"e"),
-      campaigns: UnorderedMap::new(b"a"),
-      donations: UnorderedSet::new(b"d"),
-      tokens: UnorderedMap::new(b"t".to_vec()),
-      partners: UnorderedMap::new(b"z".to_vec()),
-      total_usd: 0.0,
+  pub staged_code: LazyOption<Vec<u8>>, // Wasm waiting for approval, kept out of the state itself
+  pub staged_upgrade: Option<StagedUpgrade>, // Hash and approvals of the staged wasm
 
-      causes_count: 0,
-      events_count: 0,
-      campaigns_count: 0,
-      donations_count: 0,
-      tokens_count: 0,
-      partners_count: 0,
-    }
-  }
+  pub ft_storage_budget: Balance, // NEAR left for registering payout receivers with token contracts
+
+  pub legacy_donations: Option<UnorderedSet<DonationV0>>, // Left to move by migrate_donations
+
+  pub version: u32, // Layout version of this state, see migration.rs
 }
 
 #[near_bindgen]
 impl Contract {
   #[init]
-  pub fn new() -> Self {
-    Self {
-      gurdians: UnorderedSet::new(b"g"),
+  pub fn new(
+    owner: AccountId,
+    guardians: Vec<AccountId>,
+    tokens: Vec<TokenMetadata>,
+    config: Config,
+  ) -> Self {
+    fees::assert_fee_bps(config.fee_bps);
+    milestonevotes::assert_voting(config.milestone_quorum_bps, config.milestone_threshold_bps);
+    milestonevotes::check_vote_period(config.milestone_vote_period).or_panic();
+    guardians::check_quorum(config.grant_quorum).or_panic();
+    guardians::check_quorum(config.upgrade_quorum).or_panic();
+    let mut contract = Self {
+      owner,
+      config,
+      gurdians: UnorderedSet::new(StorageKey::Guardians),
       running: true,
-      causes: UnorderedSet::new(b"c"),
-      events: UnorderedMap::new(b"e"),
-      campaigns: UnorderedMap::new(b"a"),
-      donations: UnorderedSet::new(b"d"),
-      tokens: UnorderedMap::new(b"t".to_vec()),
-      partners: UnorderedMap::new(b"z".to_vec()),
+      causes: UnorderedMap::new(StorageKey::Causes),
+      events: UnorderedMap::new(StorageKey::Events),
+      campaigns: UnorderedMap::new(StorageKey::Campaigns),
+      donations: UnorderedMap::new(StorageKey::Donations),
+      tokens: UnorderedMap::new(StorageKey::AcceptedTokens),
+      partners: UnorderedMap::new(StorageKey::Partners),
       total_usd: 0.0,
+      total_tips_usd: 0.0,
+      cause_pools: UnorderedMap::new(StorageKey::CausePools),
+      pool_allocations: UnorderedMap::new(StorageKey::PoolAllocations),
+      treasury: UnorderedMap::new(StorageKey::Treasury),
+      fee_treasury: UnorderedMap::new(StorageKey::FeeTreasury),
+      grants: UnorderedMap::new(StorageKey::Grants),
+      matching_pools: UnorderedMap::new(StorageKey::MatchingPools),
+      target_matching_pools: LookupMap::new(StorageKey::TargetMatchingPools),
+      rounds: UnorderedMap::new(StorageKey::Rounds),
+      round_tallies: LookupMap::new(StorageKey::RoundTallies),
+      round_contributions: LookupMap::new(StorageKey::RoundContributions),
+      open_rounds: Vec::new(),
+      subscriptions: UnorderedMap::new(StorageKey::Subscriptions),
+      active_subscriptions: UnorderedSet::new(StorageKey::ActiveSubscriptions),
+      subscription_cursor: 0,
+      campaign_funds: LookupMap::new(StorageKey::CampaignFunds),
+      campaign_contributions: LookupMap::new(StorageKey::CampaignContributions),
+      milestone_voting: LookupMap::new(StorageKey::MilestoneVoting),
+      milestone_votes: LookupMap::new(StorageKey::MilestoneVotes),
+      milestone_voters: LookupMap::new(StorageKey::MilestoneVoters),
+      flags: UnorderedMap::new(StorageKey::Flags),
+      frozen: LookupMap::new(StorageKey::Frozen),
+
+      donors: LookupSet::new(StorageKey::Donors),
+      token_stats: UnorderedMap::new(StorageKey::TokenStats),
+      cause_stats: UnorderedMap::new(StorageKey::CauseStats),
+      monthly_stats: UnorderedMap::new(StorageKey::MonthlyStats),
+
+      accounts: LookupMap::new(StorageKey::Accounts),
+
+      campaign_voters: LookupMap::new(StorageKey::CampaignVoters),
+      event_voters: LookupMap::new(StorageKey::EventVoters),
+      event_attendees: LookupMap::new(StorageKey::EventAttendees),
+      campaign_anonymous_donors: LookupMap::new(StorageKey::CampaignAnonymousDonors),
+      campaign_voted: LookupSet::new(StorageKey::CampaignVoted),
+      event_anonymous_donors: LookupMap::new(StorageKey::EventAnonymousDonors),
 
       causes_count: 0,
       events_count: 0,
@@ -120,33 +299,52 @@ impl Contract {
       donations_count: 0,
       tokens_count: 0,
       partners_count: 0,
-    }
-  }
+      pool_allocations_count: 0,
+      grants_count: 0,
+      matching_pools_count: 0,
+      rounds_count: 0,
+      subscriptions_count: 0,
+      flags_count: 0,
+      donors_count: 0,
+      anonymous_donations_count: 0,
+      tips_count: 0,
 
-  pub fn add_token(&mut self, token: String, metadata: TokenMetadata) {
-    self.tokens.insert(&token, &metadata);
-    self.tokens_count += 1;
-  }
+      staged_code: LazyOption::new(StorageKey::StagedCode, None),
+      staged_upgrade: None,
 
-  pub fn get_tokens(&self) -> Vec<TokenMetadata> {
-    self.tokens.values().collect()
+      ft_storage_budget: 0,
+
+      legacy_donations: None,
+
+      version: STATE_VERSION,
+    };
+    for guardian in guardians {
+      contract.gurdians.insert(&guardian);
+    }
+    for metadata in tokens {
+      let token = tokens::token_account(&metadata.address).or_panic();
+      contract.insert_token(token, Some(metadata));
+    }
+    contract
   }
 
-  pub fn get_token(&self, address: String) -> Option<TokenMetadata> {
-    self.tokens.get(&address)
+  pub fn get_version(&self) -> u32 {
+    self.version
   }
 
   pub fn get_donations_stats(&self) -> ContractStats {
-    let stats = ContractStats {
+    ContractStats {
       causes: self.causes_count,
       events: self.events_count,
-      campaigns: self.events_count,
+      campaigns: self.campaigns_count,
       donations: self.donations_count,
       tokens: self.tokens_count,
       partners: self.partners_count,
+      donors: self.donors_count,
+      anonymous_donations: self.anonymous_donations_count,
       total_usd: self.total_usd,
-    };
-
-    return stats;
+      tips: self.tips_count,
+      tips_usd: self.total_tips_usd,
+    }
   }
 }
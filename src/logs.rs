@@ -0,0 +1,169 @@
+//! NEP-297 event logs.
+//!
+//! Every state change is logged as `EVENT_JSON:{"standard":"shiftalife","version":"1.0.0","event":<name>,"data":[<data>]}`
+//! where `data` holds one object per affected record:
+//!
+//! | event                | data                                                                            |
+//! |----------------------|---------------------------------------------------------------------------------|
+//! | `donation_received`  | `donation_id`, `donor`, `token`, `amount`, `fee`, `tip`, `amount_usd`, `target` |
+//! | `campaign_created`   | `id`, `created_by`, `cause`, `token`, `target`                                  |
+//! | `campaign_updated`   | `id`, `updated_by`, `change`                                                    |
+//! | `campaign_finalised` | `id`, `partner`, `votes`                                                        |
+//! | `event_created`      | `id`, `created_by`, `cause`, `token`, `target`                                  |
+//! | `partner_registered` | `id`, `created_by`                                                              |
+//! | `partner_verified`   | `id`, `verified`                                                                |
+//! | `vote_cast`          | `kind` (campaign or event), `id`, `partner`, `voter`                            |
+//! | `payout_sent`        | `token`, `receiver_id`, `amount`, `memo`                                        |
+//! | `refund_claimed`     | `campaign`, `account_id`, `token`, `amount`                                     |
+//!
+//! Amounts are strings (`U128`). Donors of anonymous donations are logged as `anonymous`, also as
+//! the `voter` of their votes and the `account_id` of their refunds.
+
+use crate::donations::DonationTarget;
+use crate::*;
+use near_sdk::serde_json;
+
+pub const EVENT_STANDARD: &str = "shiftalife";
+pub const EVENT_STANDARD_VERSION: &str = "1.0.0";
+
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+struct EventLog<'a, T: Serialize> {
+  standard: &'a str,
+  version: &'a str,
+  event: &'a str,
+  data: Vec<T>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DonationReceivedLog {
+  pub donation_id: String,
+  pub donor: AccountId,
+  pub token: String,
+  pub amount: U128, // Gross, the target was credited amount - fee
+  pub fee: U128,
+  pub tip: U128, // Paid on top of amount
+  pub amount_usd: f64,
+  pub target: DonationTarget,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RecordCreatedLog {
+  pub id: String,
+  pub created_by: AccountId,
+  pub cause: String,
+  pub token: String,
+  pub target: U128,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CampaignUpdatedLog {
+  pub id: String,
+  pub updated_by: AccountId,
+  pub change: String, // details, partner_added, pool_allocation, grant
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CampaignFinalisedLog {
+  pub id: String,
+  pub partner: String,
+  pub votes: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PartnerRegisteredLog {
+  pub id: String,
+  pub created_by: AccountId,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PartnerVerifiedLog {
+  pub id: String,
+  pub verified: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VoteCastLog {
+  pub kind: String, // campaign or event
+  pub id: String,
+  pub partner: String,
+  pub voter: AccountId,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PayoutSentLog {
+  pub token: String,
+  pub receiver_id: AccountId,
+  pub amount: U128,
+  pub memo: String, // What the payout is for, ie grant:3
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RefundClaimedLog {
+  pub campaign: String,
+  pub account_id: AccountId,
+  pub token: String,
+  pub amount: U128,
+}
+
+fn emit<T: Serialize>(event: &str, data: T) {
+  let log = EventLog {
+    standard: EVENT_STANDARD,
+    version: EVENT_STANDARD_VERSION,
+    event,
+    data: vec![data],
+  };
+  env::log_str(&format!(
+    "EVENT_JSON:{}",
+    serde_json::to_string(&log).unwrap()
+  ));
+}
+
+pub fn log_donation_received(data: DonationReceivedLog) {
+  emit("donation_received", data);
+}
+
+pub fn log_campaign_created(data: RecordCreatedLog) {
+  emit("campaign_created", data);
+}
+
+pub fn log_campaign_updated(data: CampaignUpdatedLog) {
+  emit("campaign_updated", data);
+}
+
+pub fn log_campaign_finalised(data: CampaignFinalisedLog) {
+  emit("campaign_finalised", data);
+}
+
+pub fn log_event_created(data: RecordCreatedLog) {
+  emit("event_created", data);
+}
+
+pub fn log_partner_registered(data: PartnerRegisteredLog) {
+  emit("partner_registered", data);
+}
+
+pub fn log_partner_verified(data: PartnerVerifiedLog) {
+  emit("partner_verified", data);
+}
+
+pub fn log_vote_cast(data: VoteCastLog) {
+  emit("vote_cast", data);
+}
+
+pub fn log_payout_sent(data: PayoutSentLog) {
+  emit("payout_sent", data);
+}
+
+pub fn log_refund_claimed(data: RefundClaimedLog) {
+  emit("refund_claimed", data);
+}
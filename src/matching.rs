@@ -0,0 +1,282 @@
+use near_sdk::Promise;
+
+use crate::constants::{
+  MATCH_DONATION_STORAGE, MATCH_ID_SEPARATOR, MAX_MATCHING_POOLS_PER_TARGET,
+  MIN_MATCHING_POOL_TOKENS, NEAR_TOKEN, ONE_TO_ONE_BPS,
+};
+use crate::donations::{check_target, Donation, DonationInput, DonationTarget};
+use crate::errors::{ensure, ContractError, OrPanic};
+use crate::logs::{log_payout_sent, PayoutSentLog};
+use crate::*;
+
+// How a sponsor matches donations, also the ft_transfer_call msg as {"matching_pool": {..}}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MatchingTerms {
+  pub target: DonationTarget, // A campaign or a cause
+  pub ratio_bps: u32,         // Matched per token donated, 10000 matches 1:1
+  pub cap: Option<U128>,      // Most matched for a single donation
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MatchingPoolMsg {
+  pub matching_pool: MatchingTerms,
+}
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MatchingPool {
+  pub id: u64,
+  pub sponsor: AccountId,
+  pub target: DonationTarget,
+  pub token: String,
+  pub ratio_bps: u32,
+  pub cap: Option<U128>,
+  pub deposit: U128, // Deposited by the sponsor
+  pub balance: U128, // Left to match with
+  pub matched: u64,  // Donations matched so far
+  pub created_on: Timestamp,
+}
+
+#[near_bindgen]
+impl Contract {
+  // Matching pools in tokens are funded with ft_transfer_call, see MatchingPoolMsg
+  #[payable]
+  pub fn create_matching_pool(&mut self, terms: MatchingTerms) -> u64 {
+    self.insert_matching_pool(
+      env::predecessor_account_id(),
+      NEAR_TOKEN.to_string(),
+      env::attached_deposit(),
+      terms,
+    )
+  }
+
+  // What was not matched goes back to the sponsor once the campaign ended or was cancelled, cause pools can be closed any time
+  pub fn refund_matching_pool(&mut self, id: u64) -> Promise {
+    let mut pool = self.find_matching_pool(id).or_panic();
+    ensure(
+      pool.sponsor == env::predecessor_account_id(),
+      ContractError::NotAllowed,
+    );
+    if let DonationTarget::Campaign { id: campaign } = &pool.target {
+      ensure(
+        !self.find_campaign(campaign).or_panic().is_active()
+          || self.is_campaign_cancelled(campaign),
+        ContractError::CampaignNotEnded,
+      );
+    }
+    let amount = pool.balance;
+    ensure(amount.0 > 0, ContractError::MatchingPoolEmpty);
+    pool.balance = U128(0);
+    self.matching_pools.insert(&id, &pool);
+    self.unlist_matching_pool(&pool);
+
+    self
+      .transfer_funds(&pool.token, pool.sponsor, amount.0)
+      .then(Self::ext(env::current_account_id()).on_matching_refund(id, amount))
+  }
+
+  #[private]
+  pub fn on_matching_refund(&mut self, id: u64, amount: U128) -> bool {
+    let mut pool = self.find_matching_pool(id).or_panic();
+    if !Self::payout_succeeded() {
+      pool.balance = U128(pool.balance.0 + amount.0);
+      self.matching_pools.insert(&id, &pool);
+      self.list_matching_pool(&pool);
+      return false;
+    }
+    log_payout_sent(PayoutSentLog {
+      token: pool.token,
+      receiver_id: pool.sponsor,
+      amount,
+      memo: format!("matching:{}", id),
+    });
+    true
+  }
+
+  pub fn get_matching_pool(&self, id: u64) -> Option<MatchingPool> {
+    self.matching_pools.get(&id)
+  }
+
+  pub fn get_matching_pools(&self, page: usize, limit: usize) -> Response<MatchingPool> {
+    let start_index = (page - 1) * limit;
+
+    let pools: Vec<MatchingPool> = self
+      .matching_pools
+      .values()
+      .skip(start_index)
+      .take(limit)
+      .collect();
+
+    Response {
+      results: pools,
+      count: self.matching_pools.len(),
+    }
+  }
+
+  // Pools that still match donations to the target
+  pub fn get_target_matching_pools(&self, target: DonationTarget) -> Vec<MatchingPool> {
+    self
+      .target_matching_pools
+      .get(&target)
+      .unwrap_or_default()
+      .iter()
+      .filter_map(|id| self.matching_pools.get(id))
+      .collect()
+  }
+}
+
+impl Contract {
+  // The sponsor's storage deposit pays for the pool record
+  pub fn insert_matching_pool(
+    &mut self,
+    sponsor: AccountId,
+    token: String,
+    amount: u128,
+    terms: MatchingTerms,
+  ) -> u64 {
+    self.check_matching_pool(&token, amount, &terms).or_panic();
+    let initial_storage = env::storage_usage();
+
+    let pool = MatchingPool {
+      id: self.matching_pools_count,
+      sponsor: sponsor.clone(),
+      target: terms.target,
+      token,
+      ratio_bps: terms.ratio_bps,
+      cap: terms.cap,
+      deposit: U128(amount),
+      balance: U128(amount),
+      matched: 0,
+      created_on: env::block_timestamp(),
+    };
+    self.matching_pools.insert(&pool.id, &pool);
+    self.list_matching_pool(&pool);
+    self.matching_pools_count += 1;
+    self.charge_storage(&sponsor, initial_storage);
+    pool.id
+  }
+
+  // A pool must hold at least a whole token, and a target takes only so many pools
+  pub fn check_matching_pool(
+    &self,
+    token: &str,
+    amount: u128,
+    terms: &MatchingTerms,
+  ) -> Result<(), ContractError> {
+    check_target(&terms.target)?;
+    if amount == 0 {
+      return Err(ContractError::MatchingPoolEmpty);
+    }
+    if amount < MIN_MATCHING_POOL_TOKENS * 10u128.pow(u32::from(self.token_decimals(token))) {
+      return Err(ContractError::MatchingPoolTooSmall);
+    }
+    if terms.ratio_bps == 0 {
+      return Err(ContractError::InvalidMatchingRatio);
+    }
+    let listed = self
+      .target_matching_pools
+      .get(&terms.target)
+      .map_or(0, |pools| pools.len());
+    if listed >= MAX_MATCHING_POOLS_PER_TARGET {
+      return Err(ContractError::MatchingPoolLimit);
+    }
+    match &terms.target {
+      DonationTarget::Campaign { id } => {
+        if !self.campaign_in_token(id, token)?.is_active() {
+          return Err(ContractError::CampaignEnded);
+        }
+        if self.is_campaign_cancelled(id) {
+          return Err(ContractError::CampaignCancelled);
+        }
+      }
+      DonationTarget::Cause { id } => {
+        self.active_cause(id)?;
+      }
+      _ => return Err(ContractError::MatchingTarget),
+    }
+    Ok(())
+  }
+
+  pub fn find_matching_pool(&self, id: u64) -> Result<MatchingPool, ContractError> {
+    self
+      .matching_pools
+      .get(&id)
+      .ok_or(ContractError::MatchingPoolNotFound)
+  }
+
+  fn list_matching_pool(&mut self, pool: &MatchingPool) {
+    let mut pools = self
+      .target_matching_pools
+      .get(&pool.target)
+      .unwrap_or_default();
+    if !pools.contains(&pool.id) {
+      pools.push(pool.id);
+      self.target_matching_pools.insert(&pool.target, &pools);
+    }
+  }
+
+  // Emptied pools leave the index, so donations only ever look at pools that can match
+  fn unlist_matching_pool(&mut self, pool: &MatchingPool) {
+    let mut pools = self
+      .target_matching_pools
+      .get(&pool.target)
+      .unwrap_or_default();
+    pools.retain(|id| *id != pool.id);
+    if pools.is_empty() {
+      self.target_matching_pools.remove(&pool.target);
+    } else {
+      self.target_matching_pools.insert(&pool.target, &pools);
+    }
+  }
+
+  // Every pool on the donation's target and token adds its share as a donation by the sponsor.
+  // The sponsor's storage deposit pays for the match, pools of sponsors out of storage are skipped.
+  pub fn match_donation(&mut self, donation: &Donation) {
+    if let DonationTarget::Campaign { id } = &donation.target {
+      if !self.find_campaign(id).or_panic().is_active() {
+        return;
+      }
+    }
+    let pools: Vec<MatchingPool> = self
+      .get_target_matching_pools(donation.target.clone())
+      .into_iter()
+      .filter(|pool| pool.token == donation.token)
+      .collect();
+
+    for mut pool in pools {
+      let mut matched = donation.amount * u128::from(pool.ratio_bps) / u128::from(ONE_TO_ONE_BPS);
+      if let Some(cap) = pool.cap {
+        matched = std::cmp::min(matched, cap.0);
+      }
+      matched = std::cmp::min(matched, pool.balance.0);
+      if matched == 0 || !self.can_store(&pool.sponsor, MATCH_DONATION_STORAGE) {
+        continue;
+      }
+      let initial_storage = env::storage_usage();
+      pool.balance = U128(pool.balance.0 - matched);
+      pool.matched += 1;
+      self.matching_pools.insert(&pool.id, &pool);
+      if pool.balance.0 == 0 {
+        self.unlist_matching_pool(&pool);
+      }
+
+      // Valued at the rate the donor gave
+      let amount_usd = donation.amount_usd * matched as f64 / donation.amount as f64;
+      self.record_donation(DonationInput {
+        id: format!("{}{}{}", donation.id, MATCH_ID_SEPARATOR, pool.id),
+        donor: pool.sponsor.clone(),
+        token: donation.token.clone(),
+        amount: U128(matched),
+        tip: 0,
+        amount_usd,
+        target: donation.target.clone(),
+        anonymous: false,
+        message: None,
+        dedication: None,
+      });
+      self.charge_storage(&pool.sponsor, initial_storage);
+    }
+  }
+}
@@ -0,0 +1,354 @@
+use std::collections::HashMap;
+
+use crate::constants::{
+  DEFAULT_GRANT_QUORUM, DEFAULT_MILESTONE_QUORUM_BPS, DEFAULT_MILESTONE_THRESHOLD_BPS,
+  DEFAULT_MILESTONE_VOTE_PERIOD, DEFAULT_UPGRADE_QUORUM, STATE_VERSION,
+};
+use crate::donations::{Donation, DonationTarget};
+use crate::errors::{ContractError, OrPanic};
+use crate::*;
+
+// The layouts below are the ones of the unversioned contract, borsh only so they must never change.
+// migrate moves its records to the current collections, donations are moved by migrate_donations.
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct CauseV0 {
+  pub title: String, // Also the id of the migrated cause, campaigns and events refer to it
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct CampaignV0 {
+  pub id: String,
+  pub created_by: AccountId,
+  pub managers: Vec<AccountId>,
+  pub title: String,
+  pub cause: String,
+  pub start_date: String,
+  pub end_date: String,
+  pub description: String,
+  pub target: u128,
+  pub current: u128,
+  pub current_usd: f64,
+  pub token: String,
+  pub voters: Vec<AccountId>, // Moved to the campaign voter set
+  pub partner: Option<String>,
+  pub partners: HashMap<String, u64>,
+  pub start_year: i32,
+  pub start_month: i32,
+  pub start_day: i32,
+  pub end_year: i32,
+  pub end_month: i32,
+  pub end_day: i32,
+  pub created_on: Timestamp,
+  pub img: String,
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct EventV0 {
+  pub id: String,
+  pub title: String,
+  pub created_by: AccountId,
+  pub managers: Vec<AccountId>,
+  pub cause: String,
+  pub date: String,
+  pub description: String,
+  pub target: u128,
+  pub current: u128,
+  pub current_usd: f64,
+  pub token: String,
+  pub attendees: Vec<AccountId>, // Moved to the event attendee set
+  pub venue: Option<String>,
+  pub event_type: String,
+  pub channel: Option<String>,
+  pub channel_url: Option<String>,
+  pub year: i32,
+  pub month: i32,
+  pub day: i32,
+  pub created_on: Timestamp,
+  pub voters: Vec<AccountId>, // Moved to the event voter set
+  pub partner: Option<String>,
+  pub partners: HashMap<String, u64>,
+  pub img: String,
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct PartnerV0 {
+  pub id: String,
+  pub created_by: AccountId,
+  pub name: String,
+  pub description: String,
+  pub website: String,
+  pub logo: String,
+  pub banner: String,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+pub struct DonationV0 {
+  pub id: String,
+  pub donor: AccountId,
+  pub token: String,
+  pub amount: u128,
+  pub amount_usd: f64,
+  pub target: String, // "campaign", "event" or "general"
+  pub event: Option<String>,
+  pub campaign: Option<String>,
+  pub created_at: Timestamp,
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct ContractV0 {
+  pub gurdians: UnorderedSet<AccountId>,
+  pub running: bool,
+  pub causes: UnorderedSet<CauseV0>,
+  pub events: UnorderedMap<String, EventV0>,
+  pub campaigns: UnorderedMap<String, CampaignV0>,
+  pub donations: UnorderedSet<DonationV0>,
+  pub tokens: UnorderedMap<String, TokenMetadata>, // Keyed by the token address
+  pub partners: UnorderedMap<String, PartnerV0>,
+  pub total_usd: f64,
+
+  pub causes_count: u64,
+  pub events_count: u64,
+  pub campaigns_count: u64,
+  pub donations_count: u64,
+  pub tokens_count: u64,
+  pub partners_count: u64,
+}
+
+impl From<CampaignV0> for Campaign {
+  fn from(old: CampaignV0) -> Self {
+    Self {
+      id: old.id,
+      created_by: old.created_by,
+      managers: old.managers,
+      title: old.title,
+      cause: old.cause,
+      start_date: old.start_date,
+      end_date: old.end_date,
+      description: old.description,
+      target: old.target,
+      current: old.current,
+      current_usd: old.current_usd,
+      token: old.token,
+      fee_bps: None,
+      fees: 0,
+      partner: old.partner,
+      partners: old.partners,
+      start_year: old.start_year,
+      start_month: old.start_month,
+      start_day: old.start_day,
+      end_year: old.end_year,
+      end_month: old.end_month,
+      end_day: old.end_day,
+      created_on: old.created_on,
+      img: old.img,
+    }
+  }
+}
+
+impl From<EventV0> for Event {
+  fn from(old: EventV0) -> Self {
+    Self {
+      id: old.id,
+      title: old.title,
+      created_by: old.created_by,
+      managers: old.managers,
+      cause: old.cause,
+      date: old.date,
+      description: old.description,
+      target: old.target,
+      current: old.current,
+      current_usd: old.current_usd,
+      token: old.token,
+      venue: old.venue,
+      event_type: old.event_type,
+      channel: old.channel,
+      channel_url: old.channel_url,
+      year: old.year,
+      month: old.month,
+      day: old.day,
+      created_on: old.created_on,
+      partner: old.partner,
+      partners: old.partners,
+      img: old.img,
+    }
+  }
+}
+
+impl From<PartnerV0> for Partner {
+  fn from(old: PartnerV0) -> Self {
+    Self {
+      id: old.id,
+      created_by: old.created_by,
+      name: old.name,
+      description: old.description,
+      website: old.website,
+      logo: old.logo,
+      banner: old.banner,
+      verified: false,
+    }
+  }
+}
+
+impl From<DonationV0> for Donation {
+  fn from(old: DonationV0) -> Self {
+    // A target that can't be told apart is taken as a general donation, as the funds are still held
+    let target = DonationTarget::from_legacy(&old.target, old.campaign, old.event, None)
+      .unwrap_or(DonationTarget::General);
+    Self {
+      id: old.id,
+      donor: old.donor,
+      token: old.token,
+      amount: old.amount,
+      fee: 0,
+      net: old.amount,
+      tip: 0,
+      amount_usd: old.amount_usd,
+      target,
+      anonymous: false,
+      message: None,
+      dedication: None,
+      message_hidden: false,
+      created_at: old.created_at,
+    }
+  }
+}
+
+#[near_bindgen]
+impl Contract {
+  // Called right after deploying new code. State of the current version is kept as it is.
+  // Over the unversioned contract the contract account becomes the owner, it should hand over with
+  // set_owner. Donations are moved afterwards with migrate_donations.
+  #[private]
+  #[init(ignore_state)]
+  pub fn migrate() -> Self {
+    let state = env::storage_read(b"STATE")
+      .ok_or(ContractError::StateNotFound)
+      .or_panic();
+    if let Ok(current) = Self::try_from_slice(&state) {
+      if current.version == STATE_VERSION {
+        return current;
+      }
+    }
+    let mut old = ContractV0::try_from_slice(&state)
+      .map_err(|_| ContractError::UnknownState)
+      .or_panic();
+    let config = Config {
+      fee_bps: 0,
+      grant_quorum: DEFAULT_GRANT_QUORUM,
+      upgrade_quorum: DEFAULT_UPGRADE_QUORUM,
+      milestone_quorum_bps: DEFAULT_MILESTONE_QUORUM_BPS,
+      milestone_threshold_bps: DEFAULT_MILESTONE_THRESHOLD_BPS,
+      milestone_vote_period: DEFAULT_MILESTONE_VOTE_PERIOD,
+      partner_voting: PartnerVoting::Equal,
+    };
+    let mut contract = Self::new(
+      env::current_account_id(),
+      old.gurdians.to_vec(),
+      Vec::new(),
+      config,
+    );
+    contract.running = old.running;
+    contract.total_usd = old.total_usd;
+
+    for cause in old.causes.iter() {
+      let id = cause.title.clone();
+      if contract.causes.get(&id).is_none() {
+        contract.causes.insert(
+          &id,
+          &Cause::new(id.clone(), cause.title, String::new(), String::new()),
+        );
+        contract.causes_count += 1;
+      }
+    }
+    for (id, campaign) in old.campaigns.iter() {
+      for voter in &campaign.voters {
+        contract.add_campaign_voter(&id, voter);
+      }
+      contract.campaigns.insert(&id, &Campaign::from(campaign));
+      contract.campaigns_count += 1;
+    }
+    for (id, event) in old.events.iter() {
+      for voter in &event.voters {
+        contract.add_event_voter(&id, voter);
+      }
+      for attendee in &event.attendees {
+        contract.add_event_attendee(&id, attendee);
+      }
+      contract.events.insert(&id, &Event::from(event));
+      contract.events_count += 1;
+    }
+    // Tokens were keyed by any string, only valid account ids can be called as token contracts
+    for (address, metadata) in old.tokens.iter() {
+      if let Ok(token) = AccountId::from_str(&address) {
+        contract.insert_token(token, Some(metadata));
+      }
+    }
+    for (id, partner) in old.partners.iter() {
+      contract.partners.insert(&id, &Partner::from(partner));
+      contract.partners_count += 1;
+    }
+
+    old.gurdians.clear();
+    old.causes.clear();
+    old.campaigns.clear();
+    old.events.clear();
+    old.tokens.clear();
+    old.partners.clear();
+    if !old.donations.is_empty() {
+      contract.legacy_donations = Some(old.donations);
+    }
+    contract
+  }
+
+  // Moves up to limit donations of the unversioned contract, returns how many are left.
+  // Stats, donors and campaign contributions are filled in as they are moved.
+  #[private]
+  pub fn migrate_donations(&mut self, limit: u64) -> u64 {
+    let mut legacy = match self.legacy_donations.take() {
+      Some(legacy) => legacy,
+      None => return 0,
+    };
+    for _ in 0..limit {
+      let old = match legacy.len().checked_sub(1) {
+        Some(last) => legacy.as_vector().get(last).unwrap(),
+        None => break,
+      };
+      legacy.remove(&old);
+      self.insert_legacy_donation(Donation::from(old));
+    }
+    let left = legacy.len();
+    if left > 0 {
+      self.legacy_donations = Some(legacy);
+    }
+    left
+  }
+}
+
+impl Contract {
+  fn insert_legacy_donation(&mut self, donation: Donation) {
+    let cause = match &donation.target {
+      DonationTarget::Campaign { id } => self.campaigns.get(id).map(|mut campaign| {
+        if campaign.token == donation.token {
+          self.add_campaign_contribution(id, &donation.donor, donation.amount);
+        } else {
+          // The campaign was credited whatever the token, the treasury holds these funds now
+          campaign.current = campaign.current.saturating_sub(donation.amount);
+          campaign.current_usd -= donation.amount_usd;
+          self.campaigns.insert(id, &campaign);
+          self.credit_treasury(&donation.token, donation.amount, donation.amount_usd);
+        }
+        campaign.cause
+      }),
+      DonationTarget::Event { id } => self.events.get(id).map(|event| event.cause),
+      _ => {
+        // General donations were only recorded, they are funds of the treasury
+        self.credit_treasury(&donation.token, donation.amount, donation.amount_usd);
+        None
+      }
+    };
+    self.record_donation_stats(&donation, cause);
+    self.donations.insert(&donation.id, &donation);
+    self.donations_count += 1;
+  }
+}
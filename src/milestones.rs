@@ -0,0 +1,481 @@
+use near_sdk::json_types::Base58CryptoHash;
+use near_sdk::Promise;
+
+use crate::constants::{anonymous, MAX_FEE_BPS};
+use crate::errors::{ensure, ContractError, OrPanic};
+use crate::flags::FlagTarget;
+use crate::logs::{log_payout_sent, log_refund_claimed, PayoutSentLog, RefundClaimedLog};
+use crate::*;
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "lowercase")]
+pub enum MilestoneStatus {
+  Pending,   // Waiting for evidence, also after a rejection
+  Submitted, // Evidence waiting for review
+  Approved,  // Its share can be withdrawn by the partner
+  Withdrawn,
+}
+
+// A milestone as defined by the campaign managers
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MilestoneTerms {
+  pub description: String,
+  pub share_bps: u16,   // Share of the campaign funds released by this milestone
+  pub evidence: String, // What the partner has to show
+}
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MilestoneEvidence {
+  pub url: String,
+  pub hash: Base58CryptoHash, // sha256 of the content behind the url
+  pub submitted_on: Timestamp,
+}
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Milestone {
+  pub description: String,
+  pub share_bps: u16,
+  pub evidence_required: String,
+  pub evidence: Option<MilestoneEvidence>, // Latest evidence submitted
+  pub status: MilestoneStatus,
+  pub approved_by: Option<AccountId>,
+  pub amount: U128, // Set when approved
+}
+
+// Where the funds of a campaign stand, campaigns without milestones use it for their release and refunds
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CampaignFunds {
+  pub milestones: Vec<Milestone>,
+  pub donated: U128, // Net donated by donors, the rest of current came from grants, rounds and cause pools
+  pub released: U128, // Approved for the partner
+  pub cancelled_on: Option<Timestamp>,
+  pub raised: U128, // Campaign funds when it was cancelled
+  pub refunded: U128,
+}
+
+impl Default for CampaignFunds {
+  fn default() -> Self {
+    Self {
+      milestones: Vec::new(),
+      donated: U128(0),
+      released: U128(0),
+      cancelled_on: None,
+      raised: U128(0),
+      refunded: U128(0),
+    }
+  }
+}
+
+#[near_bindgen]
+impl Contract {
+  // Managers set the milestones before the campaign receives funds, shares add up to all of it
+  pub fn set_campaign_milestones(&mut self, id: String, milestones: Vec<MilestoneTerms>) {
+    let campaign = self.find_campaign(&id).or_panic();
+    let caller = env::predecessor_account_id();
+    ensure(campaign.is_manager(&caller), ContractError::NotAllowed);
+    let mut funds = self.campaign_funds(&id);
+    ensure(
+      funds.cancelled_on.is_none(),
+      ContractError::CampaignCancelled,
+    );
+    ensure(campaign.current == 0, ContractError::MilestonesLocked);
+    let total: u32 = milestones
+      .iter()
+      .map(|terms| u32::from(terms.share_bps))
+      .sum();
+    ensure(
+      milestones.is_empty() || total == u32::from(MAX_FEE_BPS),
+      ContractError::InvalidMilestones,
+    );
+    let initial_storage = env::storage_usage();
+
+    funds.milestones = milestones
+      .into_iter()
+      .map(|terms| Milestone {
+        description: terms.description,
+        share_bps: terms.share_bps,
+        evidence_required: terms.evidence,
+        evidence: None,
+        status: MilestoneStatus::Pending,
+        approved_by: None,
+        amount: U128(0),
+      })
+      .collect();
+    self.campaign_funds.insert(&id, &funds);
+    self.charge_storage(&caller, initial_storage);
+    self.log_campaign_change(id, "milestones");
+  }
+
+  // The partner chosen for the campaign shows a milestone was met, submitting again replaces rejected evidence
+  pub fn submit_milestone_evidence(
+    &mut self,
+    id: String,
+    index: u64,
+    url: String,
+    hash: Base58CryptoHash,
+  ) {
+    let caller = env::predecessor_account_id();
+    ensure(
+      self.campaign_beneficiary(&id) == caller,
+      ContractError::NotAllowed,
+    );
+    let mut funds = self.campaign_funds(&id);
+    ensure(
+      funds.cancelled_on.is_none(),
+      ContractError::CampaignCancelled,
+    );
+    let milestone = find_milestone(&mut funds, index);
+    ensure(
+      milestone.status == MilestoneStatus::Pending,
+      ContractError::MilestoneNotPending,
+    );
+    let initial_storage = env::storage_usage();
+
+    milestone.evidence = Some(MilestoneEvidence {
+      url,
+      hash,
+      submitted_on: env::block_timestamp(),
+    });
+    milestone.status = MilestoneStatus::Submitted;
+    self.campaign_funds.insert(&id, &funds);
+    // Donors vote on the evidence, see milestonevotes
+    self.open_milestone_vote(&id, index);
+    self.charge_storage(&caller, initial_storage);
+    self.log_campaign_change(id, "milestone_submitted");
+  }
+
+  // Guardians can settle the evidence without waiting for the donors' vote
+  pub fn approve_milestone(&mut self, id: String, index: u64) {
+    self.assert_guardian();
+    self.approve_submitted_milestone(&id, index, Some(env::predecessor_account_id()));
+  }
+
+  pub fn reject_milestone(&mut self, id: String, index: u64) {
+    self.assert_guardian();
+    self.reject_submitted_milestone(&id, index);
+  }
+
+  // Approved shares stay the partner's even if the campaign is cancelled later
+  pub fn withdraw_milestone(&mut self, id: String, index: u64) -> Promise {
+    let receiver_id = self.campaign_beneficiary(&id);
+    ensure(
+      receiver_id == env::predecessor_account_id(),
+      ContractError::NotAllowed,
+    );
+    let campaign = self.find_campaign(&id).or_panic();
+    self.assert_not_frozen(FlagTarget::Campaign { id: id.clone() });
+    self.assert_not_frozen(FlagTarget::Partner {
+      id: campaign.partner.clone().unwrap(),
+    });
+    let mut funds = self.campaign_funds(&id);
+    let milestone = find_milestone(&mut funds, index);
+    ensure(
+      milestone.status == MilestoneStatus::Approved,
+      ContractError::MilestoneNotApproved,
+    );
+    milestone.status = MilestoneStatus::Withdrawn;
+    let amount = milestone.amount;
+    self.campaign_funds.insert(&id, &funds);
+
+    self
+      .transfer_funds(&campaign.token, receiver_id, amount.0)
+      .then(Self::ext(env::current_account_id()).on_milestone_withdrawn(id, index))
+  }
+
+  #[private]
+  pub fn on_milestone_withdrawn(&mut self, id: String, index: u64) -> bool {
+    let mut funds = self.campaign_funds(&id);
+    let milestone = find_milestone(&mut funds, index);
+    if !Self::payout_succeeded() {
+      milestone.status = MilestoneStatus::Approved;
+      self.campaign_funds.insert(&id, &funds);
+      return false;
+    }
+    log_payout_sent(PayoutSentLog {
+      token: self.find_campaign(&id).or_panic().token,
+      receiver_id: self.campaign_beneficiary(&id),
+      amount: milestone.amount,
+      memo: format!("milestone:{}:{}", id, index),
+    });
+    true
+  }
+
+  // Funds of campaigns without milestones, and whatever came in after the last milestone was approved,
+  // go to the partner in one payout
+  pub fn release_campaign_funds(&mut self, id: String) -> Promise {
+    let receiver_id = self.campaign_beneficiary(&id);
+    ensure(
+      receiver_id == env::predecessor_account_id(),
+      ContractError::NotAllowed,
+    );
+    let campaign = self.find_campaign(&id).or_panic();
+    self.assert_not_frozen(FlagTarget::Campaign { id: id.clone() });
+    self.assert_not_frozen(FlagTarget::Partner {
+      id: campaign.partner.clone().unwrap(),
+    });
+    let mut funds = self.campaign_funds(&id);
+    let amount = releasable_amount(&funds, campaign.current).or_panic();
+    funds.released = U128(funds.released.0 + amount);
+    self.campaign_funds.insert(&id, &funds);
+
+    self
+      .transfer_funds(&campaign.token, receiver_id, amount)
+      .then(Self::ext(env::current_account_id()).on_campaign_funds_released(id, U128(amount)))
+  }
+
+  #[private]
+  pub fn on_campaign_funds_released(&mut self, id: String, amount: U128) -> bool {
+    if !Self::payout_succeeded() {
+      let mut funds = self.campaign_funds(&id);
+      funds.released = U128(funds.released.0 - amount.0);
+      self.campaign_funds.insert(&id, &funds);
+      return false;
+    }
+    log_payout_sent(PayoutSentLog {
+      token: self.find_campaign(&id).or_panic().token,
+      receiver_id: self.campaign_beneficiary(&id),
+      amount,
+      memo: format!("campaign:{}", id),
+    });
+    true
+  }
+
+  // Stops the campaign, what was not released goes back to its donors
+  pub fn cancel_campaign(&mut self, id: String) {
+    let caller = env::predecessor_account_id();
+    let campaign = self.find_campaign(&id).or_panic();
+    ensure(
+      campaign.is_manager(&caller) || self.is_guardian(&caller),
+      ContractError::NotAllowed,
+    );
+    self.internal_cancel_campaign(&id);
+  }
+
+  // Donors of a cancelled campaign get back their share of the unreleased funds
+  pub fn claim_refund(&mut self, id: String) -> Promise {
+    let donor = env::predecessor_account_id();
+    let mut funds = self.campaign_funds(&id);
+    ensure(
+      funds.cancelled_on.is_some(),
+      ContractError::CampaignNotCancelled,
+    );
+    let key = (id.clone(), donor.clone());
+    let contributed = self.campaign_contributions.get(&key).unwrap_or(0);
+    let amount = refund_amount(&funds, contributed);
+    ensure(amount > 0, ContractError::NothingToRefund);
+    self.campaign_contributions.remove(&key);
+    funds.refunded = U128(funds.refunded.0 + amount);
+    self.campaign_funds.insert(&id, &funds);
+
+    let token = self.find_campaign(&id).or_panic().token;
+    self.transfer_funds(&token, donor.clone(), amount).then(
+      Self::ext(env::current_account_id()).on_refund_claimed(
+        id,
+        donor,
+        U128(contributed),
+        U128(amount),
+      ),
+    )
+  }
+
+  #[private]
+  pub fn on_refund_claimed(
+    &mut self,
+    id: String,
+    donor: AccountId,
+    contributed: U128,
+    amount: U128,
+  ) -> bool {
+    if !Self::payout_succeeded() {
+      let mut funds = self.campaign_funds(&id);
+      funds.refunded = U128(funds.refunded.0 - amount.0);
+      self.campaign_funds.insert(&id, &funds);
+      self
+        .campaign_contributions
+        .insert(&(id, donor), &contributed.0);
+      return false;
+    }
+    let account_id = if self.is_anonymous_campaign_donor(&id, &donor) {
+      anonymous()
+    } else {
+      donor
+    };
+    log_refund_claimed(RefundClaimedLog {
+      token: self.find_campaign(&id).or_panic().token,
+      campaign: id,
+      account_id,
+      amount,
+    });
+    true
+  }
+
+  pub fn get_campaign_funds(&self, id: String) -> CampaignFunds {
+    self.campaign_funds(&id)
+  }
+
+  // Net donated by the donor, what a refund is based on.
+  // Zero for donors who gave anonymously, anyone can look an account up.
+  pub fn get_campaign_contribution(&self, id: String, donor: AccountId) -> U128 {
+    if self.is_anonymous_campaign_donor(&id, &donor) {
+      return U128(0);
+    }
+    U128(self.campaign_contributions.get(&(id, donor)).unwrap_or(0))
+  }
+}
+
+impl Contract {
+  pub fn campaign_funds(&self, id: &String) -> CampaignFunds {
+    self.campaign_funds.get(id).unwrap_or_default()
+  }
+
+  // Called for every donation to a campaign, cancelled campaigns take no more donations
+  pub fn add_campaign_contribution(&mut self, id: &String, donor: &AccountId, amount: u128) {
+    let mut funds = self.campaign_funds(id);
+    ensure(
+      funds.cancelled_on.is_none(),
+      ContractError::CampaignCancelled,
+    );
+    funds.donated = U128(funds.donated.0 + amount);
+    self.campaign_funds.insert(id, &funds);
+
+    let key = (id.clone(), donor.clone());
+    let contributed = self.campaign_contributions.get(&key).unwrap_or(0);
+    self
+      .campaign_contributions
+      .insert(&key, &(contributed + amount));
+  }
+
+  pub fn is_campaign_cancelled(&self, id: &String) -> bool {
+    self
+      .campaign_funds
+      .get(id)
+      .is_some_and(|funds| funds.cancelled_on.is_some())
+  }
+
+  // Approving sets aside the milestone's share of the funds, approved_by is None when the donors approved.
+  // Shares are applied to the funds as they stand, so approving the last milestone releases all of them.
+  pub fn approve_submitted_milestone(
+    &mut self,
+    id: &String,
+    index: u64,
+    approved_by: Option<AccountId>,
+  ) {
+    let campaign = self.find_campaign(id).or_panic();
+    self.assert_not_frozen(FlagTarget::Campaign { id: id.clone() });
+    let mut funds = self.campaign_funds(id);
+    ensure(
+      funds.cancelled_on.is_none(),
+      ContractError::CampaignCancelled,
+    );
+    let released_bps: u32 = funds
+      .milestones
+      .iter()
+      .filter(|milestone| is_released(milestone))
+      .map(|milestone| u32::from(milestone.share_bps))
+      .sum();
+    let released = funds.released.0;
+    let milestone = find_milestone(&mut funds, index);
+    ensure(
+      milestone.status == MilestoneStatus::Submitted,
+      ContractError::MilestoneNotSubmitted,
+    );
+
+    let share_bps = released_bps + u32::from(milestone.share_bps);
+    let amount =
+      (campaign.current * u128::from(share_bps) / u128::from(MAX_FEE_BPS)).saturating_sub(released);
+    milestone.status = MilestoneStatus::Approved;
+    milestone.approved_by = approved_by;
+    milestone.amount = U128(amount);
+    funds.released = U128(funds.released.0 + amount);
+    self.campaign_funds.insert(id, &funds);
+    self.milestone_votes.remove(&(id.clone(), index));
+    self.log_campaign_change(id.clone(), "milestone_approved");
+  }
+
+  // Back to pending, the partner can submit new evidence
+  pub fn reject_submitted_milestone(&mut self, id: &String, index: u64) {
+    let mut funds = self.campaign_funds(id);
+    let milestone = find_milestone(&mut funds, index);
+    ensure(
+      milestone.status == MilestoneStatus::Submitted,
+      ContractError::MilestoneNotSubmitted,
+    );
+    milestone.status = MilestoneStatus::Pending;
+    self.campaign_funds.insert(id, &funds);
+    self.milestone_votes.remove(&(id.clone(), index));
+    self.log_campaign_change(id.clone(), "milestone_rejected");
+  }
+
+  // Funds that did not come from donors (grants, rounds and cause pools) go to the general treasury
+  pub fn internal_cancel_campaign(&mut self, id: &String) {
+    let campaign = self.find_campaign(id).or_panic();
+    let mut funds = self.campaign_funds(id);
+    ensure(
+      funds.cancelled_on.is_none(),
+      ContractError::CampaignCancelled,
+    );
+
+    funds.cancelled_on = Some(env::block_timestamp());
+    funds.raised = U128(campaign.current);
+    let unreleased = campaign.current - funds.released.0;
+    if campaign.current > 0 && funds.donated.0 < campaign.current {
+      let others = campaign.current - funds.donated.0;
+      let amount = others * unreleased / campaign.current;
+      let amount_usd = campaign.current_usd * amount as f64 / campaign.current as f64;
+      self.credit_treasury(&campaign.token, amount, amount_usd);
+    }
+    self.campaign_funds.insert(id, &funds);
+    self.log_campaign_change(id.clone(), "cancelled");
+  }
+
+  // Account of the partner chosen when the campaign was finalised
+  fn campaign_beneficiary(&self, id: &String) -> AccountId {
+    let partner = self
+      .find_campaign(id)
+      .or_panic()
+      .partner
+      .ok_or(ContractError::CampaignNotFinalised)
+      .or_panic();
+    self.find_partner(&partner).or_panic().created_by
+  }
+}
+
+fn is_released(milestone: &Milestone) -> bool {
+  milestone.status == MilestoneStatus::Approved || milestone.status == MilestoneStatus::Withdrawn
+}
+
+pub fn find_milestone(funds: &mut CampaignFunds, index: u64) -> &mut Milestone {
+  funds
+    .milestones
+    .get_mut(index as usize)
+    .ok_or(ContractError::MilestoneNotFound)
+    .or_panic()
+}
+
+// What release_campaign_funds can pay out, nothing while a cancelled campaign is refunding or milestones are outstanding
+pub fn releasable_amount(funds: &CampaignFunds, current: u128) -> Result<u128, ContractError> {
+  if funds.cancelled_on.is_some() {
+    return Err(ContractError::CampaignCancelled);
+  }
+  if !funds.milestones.iter().all(is_released) {
+    return Err(ContractError::MilestonesOutstanding);
+  }
+  let amount = current.saturating_sub(funds.released.0);
+  if amount == 0 {
+    return Err(ContractError::NothingToRelease);
+  }
+  Ok(amount)
+}
+
+// The donor's share of what was left unreleased when the campaign was cancelled
+pub fn refund_amount(funds: &CampaignFunds, contributed: u128) -> u128 {
+  if funds.raised.0 == 0 {
+    return 0;
+  }
+  contributed * (funds.raised.0 - funds.released.0) / funds.raised.0
+}
@@ -0,0 +1,201 @@
+use crate::constants::{MAX_FEE_BPS, MIN_MILESTONE_VOTE_PERIOD};
+use crate::errors::{ensure, ContractError, OrPanic};
+use crate::milestones::{find_milestone, MilestoneStatus};
+use crate::*;
+
+// Quorum and threshold of a campaign's milestone votes, config defaults when a campaign sets none
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MilestoneVoting {
+  pub quorum_bps: u16,    // Share of the donated weight that has to vote
+  pub threshold_bps: u16, // Share of the votes cast that has to approve
+}
+
+// Donors' vote on the evidence of a milestone, weighted by what each donor gave
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MilestoneVote {
+  pub opened_on: Timestamp, // Submitting evidence again opens a new vote
+  pub closes_on: Timestamp,
+  pub quorum_bps: u16, // Taken from the campaign when the vote opened
+  pub threshold_bps: u16,
+  pub weight: U128, // Donated to the campaign when the vote opened
+  pub approve: U128,
+  pub reject: U128,
+  pub voters: u64,
+}
+
+impl MilestoneVote {
+  pub fn reached_quorum(&self) -> bool {
+    let cast = self.approve.0 + self.reject.0;
+    cast > 0 && cast * u128::from(MAX_FEE_BPS) >= self.weight.0 * u128::from(self.quorum_bps)
+  }
+
+  pub fn approved(&self) -> bool {
+    let cast = self.approve.0 + self.reject.0;
+    self.reached_quorum()
+      && self.approve.0 * u128::from(MAX_FEE_BPS) >= cast * u128::from(self.threshold_bps)
+  }
+}
+
+#[near_bindgen]
+impl Contract {
+  pub fn set_milestone_voting_defaults(
+    &mut self,
+    quorum_bps: u16,
+    threshold_bps: u16,
+    vote_period: u64,
+  ) {
+    self.assert_owner();
+    assert_voting(quorum_bps, threshold_bps);
+    check_vote_period(vote_period).or_panic();
+    self.config.milestone_quorum_bps = quorum_bps;
+    self.config.milestone_threshold_bps = threshold_bps;
+    self.config.milestone_vote_period = vote_period;
+  }
+
+  // Like milestones, the voting rules are set before the campaign has funds
+  pub fn set_campaign_milestone_voting(&mut self, id: String, voting: Option<MilestoneVoting>) {
+    let campaign = self.find_campaign(&id).or_panic();
+    let caller = env::predecessor_account_id();
+    ensure(campaign.is_manager(&caller), ContractError::NotAllowed);
+    ensure(campaign.current == 0, ContractError::MilestonesLocked);
+    let initial_storage = env::storage_usage();
+    match voting {
+      Some(voting) => {
+        assert_voting(voting.quorum_bps, voting.threshold_bps);
+        self.milestone_voting.insert(&id, &voting);
+      }
+      None => {
+        self.milestone_voting.remove(&id);
+      }
+    }
+    self.charge_storage(&caller, initial_storage);
+    self.log_campaign_change(id, "milestone_voting");
+  }
+
+  // Donors of the campaign vote once per submitted evidence, with what they gave as weight
+  pub fn vote_milestone(&mut self, id: String, index: u64, approve: bool) {
+    let voter = env::predecessor_account_id();
+    let mut vote = self.find_milestone_vote(&id, index).or_panic();
+    ensure(
+      env::block_timestamp() < vote.closes_on,
+      ContractError::MilestoneVoteClosed,
+    );
+    let mut funds = self.campaign_funds(&id);
+    ensure(
+      find_milestone(&mut funds, index).status == MilestoneStatus::Submitted,
+      ContractError::MilestoneNotSubmitted,
+    );
+    let weight = self
+      .campaign_contributions
+      .get(&(id.clone(), voter.clone()))
+      .unwrap_or(0);
+    let key = (id.clone(), index, voter.clone());
+    ensure(
+      weight > 0 && self.milestone_voters.get(&key) != Some(vote.opened_on),
+      ContractError::VoterNotEligible,
+    );
+    let initial_storage = env::storage_usage();
+
+    if approve {
+      vote.approve = U128(vote.approve.0 + weight);
+    } else {
+      vote.reject = U128(vote.reject.0 + weight);
+    }
+    vote.voters += 1;
+    self.milestone_votes.insert(&(id.clone(), index), &vote);
+    self.milestone_voters.insert(&key, &vote.opened_on);
+    self.charge_storage(&voter, initial_storage);
+  }
+
+  // Anyone can settle a vote once it closed, without quorum the evidence is rejected.
+  // Votes are gone once settled, also when a guardian settled the evidence first.
+  pub fn close_milestone_vote(&mut self, id: String, index: u64) -> MilestoneStatus {
+    let vote = self.find_milestone_vote(&id, index).or_panic();
+    ensure(
+      env::block_timestamp() >= vote.closes_on,
+      ContractError::MilestoneVoteOpen,
+    );
+    if vote.approved() {
+      self.approve_submitted_milestone(&id, index, None);
+      return MilestoneStatus::Approved;
+    }
+    self.reject_submitted_milestone(&id, index);
+    MilestoneStatus::Pending
+  }
+
+  pub fn get_milestone_vote(&self, id: String, index: u64) -> Option<MilestoneVote> {
+    self.milestone_votes.get(&(id, index))
+  }
+
+  pub fn get_campaign_milestone_voting(&self, id: String) -> MilestoneVoting {
+    self.campaign_milestone_voting(&id)
+  }
+}
+
+impl Contract {
+  pub fn campaign_milestone_voting(&self, id: &String) -> MilestoneVoting {
+    self.milestone_voting.get(id).unwrap_or(MilestoneVoting {
+      quorum_bps: self.config.milestone_quorum_bps,
+      threshold_bps: self.config.milestone_threshold_bps,
+    })
+  }
+
+  // Called when evidence is submitted, replaces the vote on earlier evidence
+  pub fn open_milestone_vote(&mut self, id: &String, index: u64) {
+    let voting = self.campaign_milestone_voting(id);
+    let now = env::block_timestamp();
+    let vote = MilestoneVote {
+      opened_on: now,
+      closes_on: now + self.config.milestone_vote_period,
+      quorum_bps: voting.quorum_bps,
+      threshold_bps: voting.threshold_bps,
+      weight: self.campaign_funds(id).donated,
+      approve: U128(0),
+      reject: U128(0),
+      voters: 0,
+    };
+    self.milestone_votes.insert(&(id.clone(), index), &vote);
+  }
+
+  pub fn find_milestone_vote(&self, id: &str, index: u64) -> Result<MilestoneVote, ContractError> {
+    self
+      .milestone_votes
+      .get(&(id.to_string(), index))
+      .ok_or(ContractError::MilestoneVoteNotFound)
+  }
+
+  // Donations would change the weights of a vote in progress, so the campaign takes none until it closes
+  pub fn milestone_vote_open(&self, id: &String) -> bool {
+    let now = env::block_timestamp();
+    self
+      .campaign_funds(id)
+      .milestones
+      .iter()
+      .enumerate()
+      .filter(|(_, milestone)| milestone.status == MilestoneStatus::Submitted)
+      .any(|(index, _)| {
+        self
+          .find_milestone_vote(id, index as u64)
+          .is_ok_and(|vote| now < vote.closes_on)
+      })
+  }
+}
+
+pub fn check_vote_period(vote_period: u64) -> Result<(), ContractError> {
+  if vote_period < MIN_MILESTONE_VOTE_PERIOD {
+    return Err(ContractError::VotePeriodTooShort);
+  }
+  Ok(())
+}
+
+pub fn assert_voting(quorum_bps: u16, threshold_bps: u16) {
+  ensure(
+    quorum_bps > 0
+      && quorum_bps <= MAX_FEE_BPS
+      && threshold_bps > 0
+      && threshold_bps <= MAX_FEE_BPS,
+    ContractError::InvalidVoting,
+  );
+}
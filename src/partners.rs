@@ -1,3 +1,7 @@
+use crate::errors::{ensure, ContractError, OrPanic};
+use crate::logs::{
+  log_partner_registered, log_partner_verified, PartnerRegisteredLog, PartnerVerifiedLog,
+};
 use crate::*;
 
 #[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug)]
@@ -10,6 +14,7 @@ pub struct Partner {
   pub website: String,
   pub logo: String,
   pub banner: String,
+  pub verified: bool, // Verified by a guardian, only verified partners can receive grants
 }
 
 #[near_bindgen]
@@ -22,24 +27,35 @@ impl Contract {
     website: String,
     logo: String,
     banner: String,
-  ) -> String {
+  ) {
     let created_by = env::predecessor_account_id();
-    let part = self.get_partner(id.clone());
-    if part.is_none() {
-      let partner = Partner {
-        id: id.clone(),
-        created_by,
-        name,
-        description,
-        website,
-        logo,
-        banner,
-      };
-      self.partners.insert(&id.clone(), &partner);
-      self.partners_count += 1;
-      return "success".to_string();
-    }
-    return "failed".to_string();
+    ensure(
+      self.get_partner(id.clone()).is_none(),
+      ContractError::PartnerExists,
+    );
+    let initial_storage = env::storage_usage();
+    let partner = Partner {
+      id: id.clone(),
+      created_by: created_by.clone(),
+      name,
+      description,
+      website,
+      logo,
+      banner,
+      verified: false,
+    };
+    self.partners.insert(&id.clone(), &partner);
+    self.partners_count += 1;
+    self.charge_storage(&created_by, initial_storage);
+    log_partner_registered(PartnerRegisteredLog { id, created_by });
+  }
+
+  pub fn verify_partner(&mut self, id: String, verified: bool) {
+    self.assert_guardian();
+    let mut partner = self.find_partner(&id).or_panic();
+    partner.verified = verified;
+    self.partners.insert(&id, &partner);
+    log_partner_verified(PartnerVerifiedLog { id, verified });
   }
 
   pub fn get_partner(&self, id: String) -> Option<Partner> {
@@ -73,3 +89,9 @@ impl Contract {
       .collect()
   }
 }
+
+impl Contract {
+  pub fn find_partner(&self, id: &String) -> Result<Partner, ContractError> {
+    self.partners.get(id).ok_or(ContractError::PartnerNotFound)
+  }
+}
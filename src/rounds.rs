@@ -0,0 +1,275 @@
+use crate::donations::{Donation, DonationTarget};
+use crate::errors::{ensure, ContractError, OrPanic};
+use crate::flags::FlagTarget;
+use crate::*;
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "lowercase")]
+pub enum RoundStatus {
+  Open,     // Counting contributions, until ends_at
+  Tallying, // Adding up campaign scores
+  Paying,   // Crediting campaigns their share of the pool
+  Finalised,
+}
+
+// Quadratic funding round, the pool is split across campaigns by (sum of sqrt(contribution))^2 - sum of contributions
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Round {
+  pub id: u64,
+  pub title: String,
+  pub created_by: AccountId,
+  pub token: String, // Only donations in this token count
+  pub pool: U128,    // Taken from the general treasury
+  pub pool_usd: f64,
+  pub starts_at: Timestamp,
+  pub ends_at: Timestamp,
+  pub campaigns: Vec<String>, // Eligible campaigns
+  pub status: RoundStatus,
+  pub cursor: u64,      // Next campaign to tally or pay
+  pub total_score: f64, // Sum of campaign scores once tallied
+  pub distributed: U128,
+}
+
+// Contributions to one campaign during a round
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RoundTally {
+  pub sum_sqrt: f64,     // Sum of the square roots of each donor's total
+  pub contributed: U128, // Sum of the donors' totals
+  pub donors: u64,       // Unique donors
+  pub matched: U128,     // Share of the pool, set when the round is paid
+}
+
+impl Default for RoundTally {
+  fn default() -> Self {
+    Self {
+      sum_sqrt: 0.0,
+      contributed: U128(0),
+      donors: 0,
+      matched: U128(0),
+    }
+  }
+}
+
+impl RoundTally {
+  pub fn score(&self) -> f64 {
+    (self.sum_sqrt * self.sum_sqrt - self.contributed.0 as f64).max(0.0)
+  }
+}
+
+#[near_bindgen]
+impl Contract {
+  // Guardians set aside general donations as the pool of a round
+  pub fn create_round(
+    &mut self,
+    title: String,
+    token: String,
+    pool: U128,
+    starts_at: Timestamp,
+    ends_at: Timestamp,
+    campaigns: Vec<String>,
+  ) -> u64 {
+    self.assert_guardian();
+    ensure(starts_at < ends_at, ContractError::InvalidRoundWindow);
+    let eligible = self.round_campaigns(&token, campaigns).or_panic();
+    let pool_usd = self.debit_treasury(&token, pool.0);
+
+    let round = Round {
+      id: self.rounds_count,
+      title,
+      created_by: env::predecessor_account_id(),
+      token,
+      pool,
+      pool_usd,
+      starts_at,
+      ends_at,
+      campaigns: eligible,
+      status: RoundStatus::Open,
+      cursor: 0,
+      total_score: 0.0,
+      distributed: U128(0),
+    };
+    self.rounds.insert(&round.id, &round);
+    self.open_rounds.push(round.id);
+    self.rounds_count += 1;
+    round.id
+  }
+
+  // Anyone can move an ended round along, each call handles up to limit campaigns.
+  // Scores are tallied over every campaign first, then campaigns are paid.
+  pub fn finalise_round(&mut self, id: u64, limit: u64) -> RoundStatus {
+    let mut round = self.find_round(id).or_panic();
+    ensure(
+      env::block_timestamp() >= round.ends_at,
+      ContractError::RoundNotEnded,
+    );
+    let campaigns = round.campaigns.len() as u64;
+    let end = std::cmp::min(round.cursor + limit, campaigns);
+
+    match round.status {
+      RoundStatus::Open | RoundStatus::Tallying => {
+        self.open_rounds.retain(|open| *open != id);
+        for index in round.cursor..end {
+          let campaign = &round.campaigns[index as usize];
+          round.total_score += self.round_tally(id, campaign).score();
+        }
+        round.status = RoundStatus::Tallying;
+        round.cursor = end;
+        if end == campaigns {
+          round.status = RoundStatus::Paying;
+          round.cursor = 0;
+        }
+      }
+      RoundStatus::Paying => {
+        for index in round.cursor..end {
+          let campaign = round.campaigns[index as usize].clone();
+          self.pay_round_campaign(&mut round, campaign);
+        }
+        round.cursor = end;
+        if end == campaigns {
+          // Rounding leftovers and pools nobody qualified for go back to the treasury
+          let left = round.pool.0 - round.distributed.0;
+          if left > 0 {
+            let left_usd = round.pool_usd * left as f64 / round.pool.0 as f64;
+            self.credit_treasury(&round.token, left, left_usd);
+          }
+          round.status = RoundStatus::Finalised;
+        }
+      }
+      RoundStatus::Finalised => ContractError::RoundFinalised.panic(),
+    }
+    self.rounds.insert(&id, &round);
+    round.status
+  }
+
+  pub fn get_round(&self, id: u64) -> Option<Round> {
+    self.rounds.get(&id)
+  }
+
+  pub fn get_rounds(&self, page: usize, limit: usize) -> Response<Round> {
+    let start_index = (page - 1) * limit;
+
+    let rounds: Vec<Round> = self.rounds.values().skip(start_index).take(limit).collect();
+
+    Response {
+      results: rounds,
+      count: self.rounds.len(),
+    }
+  }
+
+  pub fn get_round_tally(&self, id: u64, campaign: String) -> RoundTally {
+    self.round_tally(id, &campaign)
+  }
+
+  // Zero for donors who gave to the campaign anonymously
+  pub fn get_round_contribution(&self, id: u64, campaign: String, donor: AccountId) -> U128 {
+    if self.is_anonymous_campaign_donor(&campaign, &donor) {
+      return U128(0);
+    }
+    U128(
+      self
+        .round_contributions
+        .get(&(id, campaign, donor))
+        .unwrap_or(0),
+    )
+  }
+}
+
+impl Contract {
+  pub fn find_round(&self, id: u64) -> Result<Round, ContractError> {
+    self.rounds.get(&id).ok_or(ContractError::RoundNotFound)
+  }
+
+  // Eligible campaigns without duplicates, all of them taking the round's token
+  pub fn round_campaigns(
+    &self,
+    token: &str,
+    campaigns: Vec<String>,
+  ) -> Result<Vec<String>, ContractError> {
+    let mut eligible: Vec<String> = Vec::new();
+    for campaign in campaigns {
+      if self.find_campaign(&campaign)?.token != token {
+        return Err(ContractError::TokenMismatch);
+      }
+      if !eligible.contains(&campaign) {
+        eligible.push(campaign);
+      }
+    }
+    Ok(eligible)
+  }
+
+  fn round_tally(&self, id: u64, campaign: &str) -> RoundTally {
+    self
+      .round_tallies
+      .get(&(id, campaign.to_string()))
+      .unwrap_or_default()
+  }
+
+  // Open rounds a donation to the campaign in the token counts towards
+  pub fn counting_rounds(&self, campaign: &String, token: &str) -> Vec<Round> {
+    let now = env::block_timestamp();
+    self
+      .open_rounds
+      .iter()
+      .map(|id| self.find_round(*id).or_panic())
+      .filter(|round| {
+        round.token == token
+          && now >= round.starts_at
+          && now < round.ends_at
+          && round.campaigns.contains(campaign)
+      })
+      .collect()
+  }
+
+  // Counts a donor's own donation towards every open round the campaign is in
+  pub fn add_round_contribution(&mut self, donation: &Donation) {
+    let campaign = match &donation.target {
+      DonationTarget::Campaign { id } => id.clone(),
+      _ => return,
+    };
+    for round in self.counting_rounds(&campaign, &donation.token) {
+      let id = round.id;
+      let key = (id, campaign.clone(), donation.donor.clone());
+      let before = self.round_contributions.get(&key).unwrap_or(0);
+      let after = before + donation.amount;
+      self.round_contributions.insert(&key, &after);
+
+      // Only the change in the donor's square root is added, so tallies stay O(1) per campaign
+      let mut tally = self.round_tally(id, &campaign);
+      tally.sum_sqrt += (after as f64).sqrt() - (before as f64).sqrt();
+      tally.contributed = U128(tally.contributed.0 + donation.amount);
+      if before == 0 {
+        tally.donors += 1;
+      }
+      self.round_tallies.insert(&(id, campaign.clone()), &tally);
+    }
+  }
+
+  fn pay_round_campaign(&mut self, round: &mut Round, id: String) {
+    let mut tally = self.round_tally(round.id, &id);
+    // Cancelled and frozen campaigns get nothing, their share goes back to the treasury with the leftovers
+    if round.total_score == 0.0
+      || tally.score() == 0.0
+      || self.is_campaign_cancelled(&id)
+      || self.is_frozen(FlagTarget::Campaign { id: id.clone() })
+    {
+      return;
+    }
+    let share = tally.score() / round.total_score;
+    let matched = std::cmp::min(
+      (round.pool.0 as f64 * share) as u128,
+      round.pool.0 - round.distributed.0,
+    );
+    let mut campaign = self.find_campaign(&id).or_panic();
+    campaign.current += matched;
+    campaign.current_usd += round.pool_usd * share;
+    self.campaigns.insert(&id, &campaign);
+
+    tally.matched = U128(matched);
+    self.round_tallies.insert(&(round.id, id.clone()), &tally);
+    round.distributed = U128(round.distributed.0 + matched);
+    self.log_campaign_change(id, "round");
+  }
+}
@@ -0,0 +1,98 @@
+use crate::donations::Donation;
+use crate::utils::timestamp_to_date;
+use crate::*;
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenTotals {
+  pub token: String,
+  pub amount: U128, // Raw amount in the token's own decimals
+  pub amount_usd: f64,
+  pub donations: u64,
+}
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PeriodTotals {
+  pub key: String, // Cause id or month (YYYY-MM)
+  pub amount_usd: f64,
+  pub donations: u64,
+}
+
+impl PeriodTotals {
+  pub fn new(key: String) -> Self {
+    Self {
+      key,
+      amount_usd: 0.0,
+      donations: 0,
+    }
+  }
+
+  pub fn add(&mut self, amount_usd: f64) {
+    self.amount_usd += amount_usd;
+    self.donations += 1;
+  }
+}
+
+#[near_bindgen]
+impl Contract {
+  pub fn get_token_stats(&self) -> Vec<TokenTotals> {
+    self.token_stats.values().collect()
+  }
+
+  pub fn get_cause_stats(&self) -> Vec<PeriodTotals> {
+    self.cause_stats.values().collect()
+  }
+
+  // Monthly totals in chronological order, optionally limited to one year
+  pub fn get_monthly_stats(&self, year: Option<i32>) -> Vec<PeriodTotals> {
+    let mut months: Vec<PeriodTotals> = self
+      .monthly_stats
+      .values()
+      .filter(|month| year.is_none_or(|y| month.key.starts_with(&format!("{:04}-", y))))
+      .collect();
+    months.sort_by(|a, b| a.key.cmp(&b.key));
+    months
+  }
+}
+
+impl Contract {
+  // Keeps the aggregates up to date so views never have to scan donations
+  pub fn record_donation_stats(&mut self, donation: &Donation, cause: Option<String>) {
+    if self.donors.insert(&donation.donor) {
+      self.donors_count += 1;
+    }
+
+    let mut token = self
+      .token_stats
+      .get(&donation.token)
+      .unwrap_or(TokenTotals {
+        token: donation.token.clone(),
+        amount: U128(0),
+        amount_usd: 0.0,
+        donations: 0,
+      });
+    token.amount = U128(token.amount.0 + donation.amount);
+    token.amount_usd += donation.amount_usd;
+    token.donations += 1;
+    self.token_stats.insert(&donation.token, &token);
+
+    if let Some(cause) = cause {
+      let mut totals = self
+        .cause_stats
+        .get(&cause)
+        .unwrap_or(PeriodTotals::new(cause.clone()));
+      totals.add(donation.amount_usd);
+      self.cause_stats.insert(&cause, &totals);
+    }
+
+    let date = timestamp_to_date(donation.created_at);
+    let month = format!("{:04}-{:02}", date.year, date.month);
+    let mut totals = self
+      .monthly_stats
+      .get(&month)
+      .unwrap_or(PeriodTotals::new(month.clone()));
+    totals.add(donation.amount_usd);
+    self.monthly_stats.insert(&month, &totals);
+  }
+}
@@ -0,0 +1,348 @@
+use near_sdk::{Promise, StorageUsage};
+
+use crate::constants::{
+  anonymous, MIN_SUBSCRIPTION_INTERVAL, NEAR_TOKEN, ROUND_CONTRIBUTION_STORAGE,
+  SUBSCRIPTION_DONATION_STORAGE, SUBSCRIPTION_ID_PREFIX,
+};
+use crate::donations::{check_target, DonationInput, DonationTarget};
+use crate::errors::{ensure, ContractError, OrPanic};
+use crate::flags::FlagTarget;
+use crate::logs::{log_payout_sent, PayoutSentLog};
+use crate::*;
+
+// What a donor gives every interval, also the ft_transfer_call msg as {"subscription": {..}}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SubscriptionTerms {
+  pub target: DonationTarget, // A campaign, a cause or general
+  pub amount: U128,           // Given every interval
+  pub amount_usd: f64,        // Value of each donation
+  pub interval: u64,          // Nanoseconds between donations
+  pub anonymous: Option<bool>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SubscriptionMsg {
+  pub subscription: SubscriptionTerms,
+}
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Subscription {
+  pub id: u64,
+  pub donor: AccountId,
+  pub target: DonationTarget,
+  pub token: String,
+  pub amount: U128,
+  pub amount_usd: f64,
+  pub interval: u64,
+  pub anonymous: bool,
+  pub balance: U128,       // Pre-funded, left to donate
+  pub next_due: Timestamp, // The first donation is due when the subscription is created
+  pub donations: u64,      // Donations made so far
+  pub active: bool,        // False once cancelled, out of funds or the target closed
+  pub created_on: Timestamp,
+}
+
+impl Subscription {
+  // Like donations, public views never show who is behind an anonymous subscription
+  pub fn redacted(self) -> Self {
+    if self.anonymous {
+      return Self {
+        donor: anonymous(),
+        ..self
+      };
+    }
+    self
+  }
+}
+
+#[near_bindgen]
+impl Contract {
+  // Subscriptions in tokens are funded with ft_transfer_call, see SubscriptionMsg
+  #[payable]
+  pub fn create_subscription(&mut self, terms: SubscriptionTerms) -> u64 {
+    self.insert_subscription(
+      env::predecessor_account_id(),
+      NEAR_TOKEN.to_string(),
+      env::attached_deposit(),
+      terms,
+    )
+  }
+
+  // Keepers call this to make the donations that are due, one per subscription per call.
+  // Each call looks at up to limit active subscriptions, starting where the last call stopped.
+  // Returns how many donations were made.
+  pub fn process_due_subscriptions(&mut self, limit: u64) -> u64 {
+    let now = env::block_timestamp();
+    let checks = std::cmp::min(limit, self.active_subscriptions.len());
+    let mut seen: Vec<u64> = Vec::new();
+    let mut made = 0;
+    for _ in 0..checks {
+      let active = self.active_subscriptions.len();
+      if active == 0 {
+        break;
+      }
+      let index = self.subscription_cursor % active;
+      let id = self
+        .active_subscriptions
+        .as_vector()
+        .get(index)
+        .ok_or(ContractError::SubscriptionNotFound)
+        .or_panic();
+      // Removing a subscription moves the last one into its place, which may have been seen already
+      if seen.contains(&id) {
+        break;
+      }
+      seen.push(id);
+      let mut subscription = self.find_subscription(id).or_panic();
+      if subscription.next_due <= now {
+        made += self.make_subscription_donation(&mut subscription);
+        self.subscriptions.insert(&id, &subscription);
+      }
+      if subscription.active {
+        self.subscription_cursor = index + 1;
+      } else {
+        self.active_subscriptions.remove(&id);
+        self.subscription_cursor = index;
+      }
+    }
+    made
+  }
+
+  pub fn cancel_subscription(&mut self, id: u64) {
+    let mut subscription = self.find_subscription(id).or_panic();
+    ensure(
+      subscription.donor == env::predecessor_account_id(),
+      ContractError::NotAllowed,
+    );
+    subscription.active = false;
+    self.subscriptions.insert(&id, &subscription);
+    self.active_subscriptions.remove(&id);
+  }
+
+  // Sends what was not donated back to the donor once the subscription stopped
+  pub fn withdraw_subscription(&mut self, id: u64) -> Promise {
+    let mut subscription = self.find_subscription(id).or_panic();
+    ensure(
+      subscription.donor == env::predecessor_account_id(),
+      ContractError::NotAllowed,
+    );
+    ensure(!subscription.active, ContractError::SubscriptionActive);
+    let amount = subscription.balance;
+    ensure(amount.0 > 0, ContractError::SubscriptionEmpty);
+    subscription.balance = U128(0);
+    self.subscriptions.insert(&id, &subscription);
+
+    self
+      .transfer_funds(&subscription.token, subscription.donor, amount.0)
+      .then(Self::ext(env::current_account_id()).on_subscription_withdrawn(id, amount))
+  }
+
+  #[private]
+  pub fn on_subscription_withdrawn(&mut self, id: u64, amount: U128) -> bool {
+    let mut subscription = self.find_subscription(id).or_panic();
+    if !Self::payout_succeeded() {
+      subscription.balance = U128(subscription.balance.0 + amount.0);
+      self.subscriptions.insert(&id, &subscription);
+      return false;
+    }
+    log_payout_sent(PayoutSentLog {
+      token: subscription.token,
+      receiver_id: subscription.donor,
+      amount,
+      memo: format!("subscription:{}", id),
+    });
+    true
+  }
+
+  pub fn get_subscription(&self, id: u64) -> Option<Subscription> {
+    self.subscriptions.get(&id).map(Subscription::redacted)
+  }
+
+  // Anonymous subscriptions are left out, as with get_donor_donations, get_own_subscriptions has them
+  pub fn get_donor_subscriptions(
+    &self,
+    donor: AccountId,
+    page: usize,
+    limit: usize,
+  ) -> Response<Subscription> {
+    let start_index = (page - 1) * limit;
+
+    let subscriptions: Vec<Subscription> = self
+      .subscriptions
+      .values()
+      .filter(|subscription| subscription.donor == donor && !subscription.anonymous)
+      .skip(start_index)
+      .take(limit)
+      .collect();
+
+    let subscriptions_ = self
+      .subscriptions
+      .values()
+      .filter(|subscription| subscription.donor == donor && !subscription.anonymous)
+      .count();
+
+    Response {
+      results: subscriptions,
+      count: subscriptions_ as u64,
+    }
+  }
+
+  // The caller's own subscriptions, anonymous ones included, as with get_own_donations
+  pub fn get_own_subscriptions(&mut self, page: usize, limit: usize) -> Response<Subscription> {
+    let donor = env::predecessor_account_id();
+    let start_index = (page - 1) * limit;
+
+    let subscriptions: Vec<Subscription> = self
+      .subscriptions
+      .values()
+      .filter(|subscription| subscription.donor == donor)
+      .collect();
+
+    Response {
+      count: subscriptions.len() as u64,
+      results: subscriptions
+        .into_iter()
+        .skip(start_index)
+        .take(limit)
+        .collect(),
+    }
+  }
+}
+
+impl Contract {
+  // The donor's storage deposit pays for the subscription record
+  pub fn insert_subscription(
+    &mut self,
+    donor: AccountId,
+    token: String,
+    deposit: u128,
+    terms: SubscriptionTerms,
+  ) -> u64 {
+    check_target(&terms.target).or_panic();
+    ensure(
+      self.accepts_subscription(&terms.target),
+      ContractError::SubscriptionTarget,
+    );
+    ensure(
+      terms.amount.0 > 0
+        && deposit >= terms.amount.0
+        && terms.interval >= MIN_SUBSCRIPTION_INTERVAL,
+      ContractError::InvalidSubscription,
+    );
+    if let DonationTarget::Campaign { id } = &terms.target {
+      self.campaign_in_token(id, &token).or_panic();
+    }
+    let initial_storage = env::storage_usage();
+
+    let now = env::block_timestamp();
+    let subscription = Subscription {
+      id: self.subscriptions_count,
+      donor: donor.clone(),
+      target: terms.target,
+      token,
+      amount: terms.amount,
+      amount_usd: terms.amount_usd,
+      interval: terms.interval,
+      anonymous: terms.anonymous.unwrap_or(false),
+      balance: U128(deposit),
+      next_due: now,
+      donations: 0,
+      active: true,
+      created_on: now,
+    };
+    self.subscriptions.insert(&subscription.id, &subscription);
+    self.active_subscriptions.insert(&subscription.id);
+    self.subscriptions_count += 1;
+    self.charge_storage(&donor, initial_storage);
+    subscription.id
+  }
+
+  pub fn find_subscription(&self, id: u64) -> Result<Subscription, ContractError> {
+    self
+      .subscriptions
+      .get(&id)
+      .ok_or(ContractError::SubscriptionNotFound)
+  }
+
+  // A donor without storage left for the record misses that donation, they can top up before the next one.
+  // Donations to a campaign frozen under a flag or voting on milestone evidence are missed as well. Returns 1 if a donation was made.
+  fn make_subscription_donation(&mut self, subscription: &mut Subscription) -> u64 {
+    subscription.next_due += subscription.interval;
+    if !self.accepts_subscription(&subscription.target) {
+      subscription.active = false;
+      return 0;
+    }
+    // Checked up front, a donation failing to charge storage would revert the whole batch
+    if !self.can_store(
+      &subscription.donor,
+      self.subscription_donation_storage(subscription),
+    ) || self.is_subscription_target_frozen(&subscription.target)
+      || self.is_subscription_target_voting(&subscription.target)
+    {
+      return 0;
+    }
+    self.create_donation(DonationInput {
+      id: format!(
+        "{}{}:{}",
+        SUBSCRIPTION_ID_PREFIX, subscription.id, subscription.donations
+      ),
+      donor: subscription.donor.clone(),
+      token: subscription.token.clone(),
+      amount: subscription.amount,
+      tip: 0,
+      amount_usd: subscription.amount_usd,
+      target: subscription.target.clone(),
+      anonymous: subscription.anonymous,
+      message: None,
+      dedication: None,
+    });
+    subscription.balance = U128(subscription.balance.0 - subscription.amount.0);
+    subscription.donations += 1;
+    if subscription.balance.0 < subscription.amount.0 {
+      subscription.active = false;
+    }
+    1
+  }
+
+  // Worst case storage of the next donation, each open round it counts towards adds a contribution
+  pub fn subscription_donation_storage(&self, subscription: &Subscription) -> StorageUsage {
+    let id = match &subscription.target {
+      DonationTarget::Campaign { id } => id,
+      _ => return SUBSCRIPTION_DONATION_STORAGE,
+    };
+    let rounds = self.counting_rounds(id, &subscription.token).len() as StorageUsage;
+    let ids = (2 * id.len() + subscription.donor.as_str().len()) as StorageUsage;
+    SUBSCRIPTION_DONATION_STORAGE + rounds * (ROUND_CONTRIBUTION_STORAGE + ids)
+  }
+
+  fn accepts_subscription(&self, target: &DonationTarget) -> bool {
+    match target {
+      DonationTarget::Campaign { id } => self
+        .campaigns
+        .get(id)
+        .is_some_and(|campaign| campaign.is_active() && !self.is_campaign_cancelled(id)),
+      DonationTarget::Cause { id } => self.causes.get(id).is_some_and(|cause| cause.active),
+      DonationTarget::General => true,
+      _ => false,
+    }
+  }
+
+  fn is_subscription_target_voting(&self, target: &DonationTarget) -> bool {
+    match target {
+      DonationTarget::Campaign { id } => self.milestone_vote_open(id),
+      _ => false,
+    }
+  }
+
+  fn is_subscription_target_frozen(&self, target: &DonationTarget) -> bool {
+    match target {
+      DonationTarget::Campaign { id } => self
+        .frozen
+        .contains_key(&FlagTarget::Campaign { id: id.clone() }),
+      _ => false,
+    }
+  }
+}
@@ -2,12 +2,39 @@
 
 pub mod tests {
   // use super::*;
+  use crate::campaign::PartnerVoting;
+  use crate::causepools::{PoolBalance, PoolShare};
   use crate::constants::*;
+  use crate::donations::{
+    check_donation_id, check_note, check_target, Dedication, DedicationKind, Donation,
+    DonationInput, DonationTarget,
+  };
+  use crate::errors::*;
+  use crate::flags::{FlagStatus, FlagTarget};
+  use crate::fungibletoken::DonationMsg;
+  use crate::guardians::check_quorum;
+  use crate::logs::{EVENT_STANDARD, EVENT_STANDARD_VERSION};
+  use crate::matching::MatchingTerms;
+  use crate::migration::{CampaignV0, CauseV0, ContractV0, DonationV0, EventV0, PartnerV0};
+  use crate::milestones::{releasable_amount, MilestoneStatus, MilestoneTerms};
+  use crate::milestonevotes::{check_vote_period, MilestoneVoting};
+  use crate::rounds::RoundStatus;
+  use crate::subscriptions::{SubscriptionMsg, SubscriptionTerms};
+  use crate::tokens::{check_token_metadata, token_account};
+  use crate::treasury::{GrantRecipient, GrantStatus};
+  use crate::upgrade::migrate_gas;
   use crate::*;
-  use near_sdk::json_types::U128;
+  use near_contract_standards::storage_management::StorageManagement;
+  use near_sdk::borsh::{BorshDeserialize, BorshSerialize};
+  use near_sdk::collections::{UnorderedMap, UnorderedSet};
+  use near_sdk::json_types::{Base58CryptoHash, U128};
+  use near_sdk::serde_json::{json, Value};
   use near_sdk::test_utils::test_env::alice;
-  use near_sdk::test_utils::VMContextBuilder;
-  use near_sdk::{testing_env, VMContext, ONE_NEAR};
+  use near_sdk::test_utils::{get_logs, VMContextBuilder};
+  use near_sdk::{
+    testing_env, CryptoHash, Gas, PromiseOrValue, PromiseResult, VMContext, ONE_NEAR,
+  };
+  use std::collections::HashMap;
 
   pub fn get_context(is_view: bool) -> VMContext {
     VMContextBuilder::new()
@@ -25,13 +52,2721 @@ pub mod tests {
     assert!(true);
   }
 
+  fn config() -> Config {
+    Config {
+      fee_bps: 0,
+      grant_quorum: 1,
+      upgrade_quorum: 1,
+      milestone_quorum_bps: 5_000,
+      milestone_threshold_bps: 6_000,
+      milestone_vote_period: MIN_MILESTONE_VOTE_PERIOD,
+      partner_voting: PartnerVoting::Equal,
+    }
+  }
+
+  fn new_contract(guardians: Vec<AccountId>) -> Contract {
+    Contract::new(master(), guardians, Vec::new(), config())
+  }
+
   #[test]
   fn test_func_sample() {
     let _context = get_context(false);
     testing_env!(_context);
 
-    let mut contract = Contract::new();
-    
+    let contract = new_contract(Vec::new());
+    assert_eq!(contract.get_campaigns(1, 10).count, 0);
+  }
+
+  fn set_caller(predecessor: AccountId, deposit: u128, timestamp: u64) {
+    testing_env!(VMContextBuilder::new()
+      .current_account_id(master())
+      .predecessor_account_id(predecessor)
+      .attached_deposit(deposit)
+      .block_timestamp(timestamp)
+      .build());
+  }
+
+  // Parses the NEP-297 logs of the last call
+  fn event_logs() -> Vec<Value> {
+    get_logs()
+      .iter()
+      .map(|log| {
+        assert!(log.starts_with("EVENT_JSON:"));
+        let event: Value = near_sdk::serde_json::from_str(&log["EVENT_JSON:".len()..]).unwrap();
+        assert_eq!(event["standard"], EVENT_STANDARD);
+        assert_eq!(event["version"], EVENT_STANDARD_VERSION);
+        event
+      })
+      .collect()
+  }
+
+  fn register(contract: &mut Contract, account_id: AccountId) {
+    set_caller(account_id, ONE_NEAR, 0);
+    contract.storage_deposit(None, None);
+  }
+
+  // A contract with a guardian, a cause, a verified partner and a campaign ending 2022-12-31
+  fn setup() -> Contract {
+    set_caller(master(), 0, 0);
+    let mut contract = new_contract(vec![master()]);
+    for account_id in [alice(), supercode(), dalmasonto()] {
+      register(&mut contract, account_id);
+    }
+    set_caller(master(), 0, 0);
+    contract.create_cause(
+      "water".to_string(),
+      "Water".to_string(),
+      "Clean water".to_string(),
+      "icon".to_string(),
+    );
+    set_caller(alice(), 0, 0);
+    contract.register_as_partner(
+      "redcross".to_string(),
+      "Red Cross".to_string(),
+      "Relief".to_string(),
+      "redcross.org".to_string(),
+      "logo".to_string(),
+      "banner".to_string(),
+    );
+    set_caller(supercode(), 0, 0);
+    contract.create_campaign(
+      "wells".to_string(),
+      "Wells".to_string(),
+      "water".to_string(),
+      "Dig wells".to_string(),
+      U128(100 * ONE_NEAR),
+      NEAR_TOKEN.to_string(),
+      "2022-01-01".to_string(),
+      "2022,1,1".to_string(),
+      "2022-12-31".to_string(),
+      "2022,12,31".to_string(),
+      "img".to_string(),
+    );
+    contract
+  }
+
+  // A second campaign of the water cause, taking usdn instead of near
+  fn usdn_campaign(contract: &mut Contract, id: &str) {
+    set_caller(supercode(), 0, 0);
+    contract.create_campaign(
+      id.to_string(),
+      "Wells in usdn".to_string(),
+      "water".to_string(),
+      "Dig wells".to_string(),
+      U128(100),
+      usdn().to_string(),
+      "2022-01-01".to_string(),
+      "2022,1,1".to_string(),
+      "2022-12-31".to_string(),
+      "2022,12,31".to_string(),
+      "img".to_string(),
+    );
+  }
+
+  // Flags the target and has the guardian freeze it
+  fn freeze(contract: &mut Contract, target: FlagTarget) -> u64 {
+    set_caller(dalmasonto(), FLAG_BOND, 0);
+    let id = contract.flag(target, "Fake".to_string(), "ipfs://report".to_string());
+    set_caller(master(), 0, 0);
+    contract.freeze_flagged(id);
+    id
+  }
+
+  #[test]
+  fn test_campaign_created_log() {
+    setup();
+    let logs = event_logs();
+    assert_eq!(logs.len(), 1);
+    assert_eq!(logs[0]["event"], "campaign_created");
+    assert_eq!(
+      logs[0]["data"][0],
+      json!({
+        "id": "wells",
+        "created_by": supercode(),
+        "cause": "water",
+        "token": NEAR_TOKEN,
+        "target": (100 * ONE_NEAR).to_string(),
+      })
+    );
+  }
+
+  #[test]
+  fn test_partner_logs() {
+    let mut contract = setup();
+    set_caller(master(), 0, 0);
+    contract.verify_partner("redcross".to_string(), true);
+    let logs = event_logs();
+    assert_eq!(logs[0]["event"], "partner_verified");
+    assert_eq!(
+      logs[0]["data"][0],
+      json!({ "id": "redcross", "verified": true })
+    );
+
+    set_caller(dalmasonto(), 0, 0);
+    contract.register_as_partner(
+      "unicef".to_string(),
+      "Unicef".to_string(),
+      "Children".to_string(),
+      "unicef.org".to_string(),
+      "logo".to_string(),
+      "banner".to_string(),
+    );
+    let logs = event_logs();
+    assert_eq!(logs[0]["event"], "partner_registered");
+    assert_eq!(
+      logs[0]["data"][0],
+      json!({ "id": "unicef", "created_by": dalmasonto() })
+    );
+  }
+
+  #[test]
+  fn test_anonymous_donation_log_hides_donor() {
+    let mut contract = setup();
+    set_caller(dalmasonto(), ONE_NEAR, 0);
+    contract.near_donation(
+      "d1".to_string(),
+      U128(ONE_NEAR),
+      5.0,
+      DonationTarget::Campaign {
+        id: "wells".to_string(),
+      },
+      Some(true),
+      None,
+      None,
+      None,
+    );
+    let logs = event_logs();
+    assert_eq!(logs.len(), 1);
+    assert_eq!(logs[0]["event"], "donation_received");
+    assert_eq!(
+      logs[0]["data"][0],
+      json!({
+        "donation_id": "d1",
+        "donor": ANONYMOUS_DONOR,
+        "token": NEAR_TOKEN,
+        "amount": ONE_NEAR.to_string(),
+        "fee": "0",
+        "tip": "0",
+        "amount_usd": 5.0,
+        "target": { "type": "campaign", "id": "wells" },
+      })
+    );
+  }
+
+  #[test]
+  fn test_anonymous_donors_stay_hidden() {
+    let mut contract = setup();
+    set_caller(supercode(), 0, 0);
+    contract.add_campaign_partner("wells".to_string(), "redcross".to_string());
+    for (donor, id, anonymous) in [(dalmasonto(), "d1", true), (alice(), "d2", false)] {
+      set_caller(donor, ONE_NEAR, 0);
+      contract.near_donation(
+        id.to_string(),
+        U128(ONE_NEAR),
+        5.0,
+        DonationTarget::Campaign {
+          id: "wells".to_string(),
+        },
+        Some(anonymous),
+        None,
+        None,
+        None,
+      );
+    }
+    // Listings and the leaderboard show the placeholder instead of the donor
+    let donors: Vec<AccountId> = contract
+      .get_campaign_donations("wells".to_string(), 1, 10)
+      .results
+      .into_iter()
+      .map(|details| details.donation.donor)
+      .collect();
+    assert_eq!(donors, vec![anonymous(), alice()]);
+    let leaderboard = contract.get_campaign_leaderboard("wells".to_string(), 10);
+    assert!(leaderboard.iter().any(|total| total.donor == anonymous()));
+    assert!(leaderboard.iter().all(|total| total.donor != dalmasonto()));
+    assert_eq!(contract.get_donations_stats().anonymous_donations, 1);
+    let voters = contract.get_campaign_voters("wells".to_string(), 1, 10);
+    assert_eq!((voters.count, voters.results), (1, vec![alice()]));
+    assert_eq!(contract.get_donor_donations(dalmasonto(), 1, 10).count, 0);
+    assert_eq!(contract.get_donor_donations(alice(), 1, 10).count, 1);
+    // Only the donor's own listing has it, under their name
+    set_caller(dalmasonto(), 0, 0);
+    let own = contract.get_own_donations(1, 10);
+    assert_eq!(own.count, 1);
+    assert_eq!(own.results[0].donation.donor, dalmasonto());
+    assert!(own.results[0].donation.anonymous);
+    set_caller(alice(), 0, 0);
+    assert_eq!(contract.get_own_donations(1, 10).count, 1);
+    assert_eq!(
+      contract.get_campaign_contribution("wells".to_string(), dalmasonto()),
+      U128(0)
+    );
+    assert_eq!(
+      contract.get_campaign_contribution("wells".to_string(), alice()),
+      U128(ONE_NEAR)
+    );
+
+    // The anonymous donor still votes once, the log doesn't name them
+    set_caller(dalmasonto(), 0, 0);
+    contract.campaign_vote("wells".to_string(), "redcross".to_string());
+    assert_eq!(event_logs()[0]["data"][0]["voter"], ANONYMOUS_DONOR);
+    assert_eq!(
+      contract.get_campaign("wells".to_string()).unwrap().partners["redcross"],
+      1
+    );
+    assert_eq!(
+      contract
+        .campaign_anonymous_donors
+        .get(&("wells".to_string(), dalmasonto())),
+      Some(false)
+    );
+
+    // Anonymous subscriptions are redacted the same way
+    set_caller(dalmasonto(), 100, 0);
+    let id = contract.create_subscription(SubscriptionTerms {
+      target: DonationTarget::General,
+      amount: U128(100),
+      amount_usd: 1.0,
+      interval: MIN_SUBSCRIPTION_INTERVAL,
+      anonymous: Some(true),
+    });
+    assert_eq!(
+      contract.get_subscription(id).unwrap().donor.to_string(),
+      ANONYMOUS_DONOR
+    );
+    assert_eq!(
+      contract.get_donor_subscriptions(dalmasonto(), 1, 10).count,
+      0
+    );
+    let own = contract.get_own_subscriptions(1, 10);
+    assert_eq!(own.count, 1);
+    assert_eq!(own.results[0].donor, dalmasonto());
+  }
+
+  #[test]
+  fn test_donation_message_and_dedication() {
+    let mut contract = setup();
+    set_caller(dalmasonto(), ONE_NEAR, 0);
+    contract.near_donation(
+      "d1".to_string(),
+      U128(ONE_NEAR),
+      5.0,
+      DonationTarget::Campaign {
+        id: "wells".to_string(),
+      },
+      None,
+      Some("Keep digging".to_string()),
+      Some(Dedication {
+        kind: DedicationKind::Memory,
+        name: "Grandma".to_string(),
+      }),
+      None,
+    );
+    let donation = contract.get_donation("d1".to_string()).unwrap().donation;
+    assert_eq!(donation.message, Some("Keep digging".to_string()));
+    let dedication = donation.dedication.unwrap();
+    assert_eq!(
+      (dedication.kind, dedication.name),
+      (DedicationKind::Memory, "Grandma".to_string())
+    );
+
+    // The campaign manager hides the message, the dedication stays
+    set_caller(supercode(), 0, 0);
+    contract.hide_donation_message("d1".to_string(), true);
+    let donation = contract.get_donation("d1".to_string()).unwrap().donation;
+    assert!(donation.message.is_none());
+    assert!(donation.dedication.is_some());
+    let listed = contract.get_donor_donations(dalmasonto(), 1, 10).results;
+    assert!(listed[0].donation.message.is_none());
+  }
+
+  #[test]
+  fn test_note_limits() {
+    let message = |len| Some("a".repeat(len));
+    let dedication = |len| {
+      Some(Dedication {
+        kind: DedicationKind::Honour,
+        name: "a".repeat(len),
+      })
+    };
+    assert!(check_note(
+      &message(MAX_MESSAGE_LENGTH),
+      &dedication(MAX_DEDICATION_LENGTH)
+    )
+    .is_ok());
+    assert_eq!(
+      check_note(&message(MAX_MESSAGE_LENGTH + 1), &None),
+      Err(ContractError::MessageTooLong)
+    );
+    assert_eq!(
+      check_note(&None, &dedication(MAX_DEDICATION_LENGTH + 1)),
+      Err(ContractError::DedicationTooLong)
+    );
+  }
+
+  #[test]
+  fn test_vote_and_finalise_logs() {
+    let mut contract = setup();
+    set_caller(supercode(), 0, 0);
+    contract.add_campaign_partner("wells".to_string(), "redcross".to_string());
+    assert_eq!(event_logs()[0]["event"], "campaign_updated");
+    assert_eq!(event_logs()[0]["data"][0]["change"], "partner_added");
+    // Only managers and guardians add candidates, a candidate is added once
+    let add = |contract: &mut Contract| {
+      contract.internal_add_campaign_partner(&"wells".to_string(), "redcross".to_string())
+    };
+    assert_eq!(
+      add(&mut contract),
+      Err(ContractError::PartnerAlreadyCandidate)
+    );
+    set_caller(alice(), 0, 0);
+    assert_eq!(add(&mut contract), Err(ContractError::NotAllowed));
+
+    set_caller(dalmasonto(), ONE_NEAR, 0);
+    contract.near_donation(
+      "d1".to_string(),
+      U128(ONE_NEAR),
+      5.0,
+      DonationTarget::Campaign {
+        id: "wells".to_string(),
+      },
+      None,
+      None,
+      None,
+      None,
+    );
+    contract.campaign_vote("wells".to_string(), "redcross".to_string());
+    let logs = event_logs();
+    assert_eq!(logs.len(), 2);
+    assert_eq!(logs[0]["data"][0]["donor"], dalmasonto().to_string());
+    assert_eq!(logs[1]["event"], "vote_cast");
+    assert_eq!(
+      logs[1]["data"][0],
+      json!({ "kind": "campaign", "id": "wells", "partner": "redcross", "voter": dalmasonto() })
+    );
+
+    // 2023-01-01, the day after the campaign ended
+    set_caller(supercode(), 0, 1_672_531_200_000_000_000);
+    assert_eq!(contract.finalize_campaign("wells".to_string()), "redcross");
+    let logs = event_logs();
+    assert_eq!(logs[0]["event"], "campaign_finalised");
+    assert_eq!(
+      logs[0]["data"][0],
+      json!({ "id": "wells", "partner": "redcross", "votes": 1 })
+    );
+  }
+
+  #[test]
+  fn test_donation_stats() {
+    let mut contract = setup();
+    donate(&mut contract, "d1", ONE_NEAR);
+    // 2022-03-15, anonymously to the general fund
+    set_caller(alice(), 2 * ONE_NEAR, 1_647_302_400_000_000_000);
+    contract.near_donation(
+      "d2".to_string(),
+      U128(2 * ONE_NEAR),
+      20.0,
+      DonationTarget::General,
+      Some(true),
+      None,
+      None,
+      None,
+    );
+
+    let stats = contract.get_donations_stats();
+    assert_eq!((stats.campaigns, stats.causes, stats.partners), (1, 1, 1));
+    assert_eq!(
+      (stats.donations, stats.donors, stats.anonymous_donations),
+      (2, 2, 1)
+    );
+    assert_eq!(stats.total_usd, 30.0);
+
+    let tokens = contract.get_token_stats();
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(
+      (
+        tokens[0].token.as_str(),
+        tokens[0].amount.0,
+        tokens[0].donations
+      ),
+      (NEAR_TOKEN, 3 * ONE_NEAR, 2)
+    );
+    // General donations have no cause
+    let causes = contract.get_cause_stats();
+    assert_eq!(causes.len(), 1);
+    assert_eq!(
+      (
+        causes[0].key.as_str(),
+        causes[0].amount_usd,
+        causes[0].donations
+      ),
+      ("water", 10.0, 1)
+    );
+    let months: Vec<String> = contract
+      .get_monthly_stats(None)
+      .into_iter()
+      .map(|month| month.key)
+      .collect();
+    assert_eq!(months, vec!["1970-01", "2022-03"]);
+    let months = contract.get_monthly_stats(Some(2022));
+    assert_eq!((months.len(), months[0].amount_usd), (1, 20.0));
+  }
+
+  #[test]
+  fn test_causes() {
+    let mut contract = setup();
+    set_caller(master(), 0, 0);
+    contract.create_cause(
+      "trees".to_string(),
+      "Trees".to_string(),
+      "Tree planting".to_string(),
+      "icon".to_string(),
+    );
+    contract.update_cause(
+      "trees".to_string(),
+      "Forests".to_string(),
+      "Reforestation".to_string(),
+      "leaf".to_string(),
+    );
+    let cause = contract.get_cause("trees".to_string()).unwrap();
+    assert_eq!(
+      (cause.title.as_str(), cause.icon.as_str(), cause.created_by),
+      ("Forests", "leaf", master())
+    );
+    assert_eq!(contract.get_causes().len(), 2);
+    assert_eq!(contract.get_donations_stats().causes, 2);
+
+    // Campaigns are listed under their cause
+    let campaigns = contract.get_cause_campaigns("water".to_string(), 1, 10);
+    assert_eq!(
+      (campaigns.count, campaigns.results[0].id.as_str()),
+      (1, "wells")
+    );
+    assert_eq!(
+      contract
+        .get_cause_campaigns("trees".to_string(), 1, 10)
+        .count,
+      0
+    );
+
+    // Archived causes take nothing new until restored
+    contract.archive_cause("trees".to_string());
+    assert_eq!(
+      contract.active_cause(&"trees".to_string()).err(),
+      Some(ContractError::CauseArchived)
+    );
+    assert_eq!(
+      contract.active_cause(&"dust".to_string()).err(),
+      Some(ContractError::CauseNotFound)
+    );
+    contract.restore_cause("trees".to_string());
+    assert!(contract.active_cause(&"trees".to_string()).is_ok());
+  }
+
+  #[test]
+  fn test_cause_pool_allocation() {
+    let mut contract = setup();
+    set_caller(dalmasonto(), 3 * ONE_NEAR, 0);
+    contract.near_donation(
+      "d1".to_string(),
+      U128(3 * ONE_NEAR),
+      30.0,
+      DonationTarget::Cause {
+        id: "water".to_string(),
+      },
+      None,
+      None,
+      None,
+      None,
+    );
+    let pool = contract.get_cause_pool("water".to_string()).unwrap();
+    assert_eq!(pool.balances[NEAR_TOKEN].amount.0, 3 * ONE_NEAR);
+
+    set_caller(master(), 0, 0);
+    contract.allocate_cause_pool(
+      "water".to_string(),
+      NEAR_TOKEN.to_string(),
+      vec![PoolShare {
+        campaign: "wells".to_string(),
+        amount: U128(ONE_NEAR),
+      }],
+    );
+    let wells = contract.get_campaign("wells".to_string()).unwrap();
+    assert_eq!((wells.current, wells.current_usd), (ONE_NEAR, 10.0));
+    let pool = contract.get_cause_pool("water".to_string()).unwrap();
+    assert_eq!(
+      (
+        pool.balances[NEAR_TOKEN].amount.0,
+        pool.allocated[NEAR_TOKEN].amount.0
+      ),
+      (2 * ONE_NEAR, ONE_NEAR)
+    );
+    let allocations = contract.get_pool_allocations("water".to_string(), 1, 10);
+    assert_eq!(
+      (allocations.count, allocations.results[0].amount.0),
+      (1, ONE_NEAR)
+    );
+
+    // Only active campaigns of the cause, in the pool's token and not frozen
+    let share = |contract: &Contract, campaign: &str| {
+      contract
+        .pool_share_campaign("water", NEAR_TOKEN, &campaign.to_string())
+        .err()
+    };
+    usdn_campaign(&mut contract, "wells2");
+    assert_eq!(
+      share(&contract, "wells2"),
+      Some(ContractError::TokenMismatch)
+    );
+    freeze(
+      &mut contract,
+      FlagTarget::Campaign {
+        id: "wells".to_string(),
+      },
+    );
+    assert_eq!(share(&contract, "wells"), Some(ContractError::Frozen));
+    set_caller(master(), 0, 1_672_531_200_000_000_000);
+    assert_eq!(
+      share(&contract, "wells"),
+      Some(ContractError::CampaignEnded)
+    );
+
+    // Nothing is taken from a pool, an empty one included, so no share is valued at NaN
+    let mut empty = PoolBalance {
+      amount: U128(0),
+      amount_usd: 0.0,
+    };
+    assert_eq!(empty.take(0), Err(ContractError::ZeroAmount));
+  }
+
+  #[test]
+  fn test_treasury_grants() {
+    let mut contract = setup();
+    set_caller(dalmasonto(), 2 * ONE_NEAR, 0);
+    contract.near_donation(
+      "d1".to_string(),
+      U128(2 * ONE_NEAR),
+      20.0,
+      DonationTarget::General,
+      None,
+      None,
+      None,
+      None,
+    );
+    let wells = GrantRecipient::Campaign {
+      id: "wells".to_string(),
+    };
+    set_caller(master(), 0, 0);
+    let id = contract.propose_grant(
+      wells.clone(),
+      NEAR_TOKEN.to_string(),
+      U128(ONE_NEAR),
+      "Pumps".to_string(),
+    );
+    contract.approve_grant(id);
+    assert!(matches!(
+      contract.execute_grant(id),
+      PromiseOrValue::Value(true)
+    ));
+    assert_eq!(
+      contract.get_campaign("wells".to_string()).unwrap().current,
+      ONE_NEAR
+    );
+    assert_eq!(
+      contract.get_grant(id).unwrap().status,
+      GrantStatus::Executed
+    );
+    assert_eq!(contract.treasury_balance(&NEAR_TOKEN.to_string()), ONE_NEAR);
+
+    // Partners are paid out, a failed transfer puts the funds back
+    let redcross = GrantRecipient::Partner {
+      id: "redcross".to_string(),
+    };
+    assert_eq!(
+      contract.grant_recipient_ok(&redcross, NEAR_TOKEN),
+      Err(ContractError::PartnerNotVerified)
+    );
+    contract.verify_partner("redcross".to_string(), true);
+    let id = contract.propose_grant(
+      redcross,
+      NEAR_TOKEN.to_string(),
+      U128(ONE_NEAR),
+      "Relief".to_string(),
+    );
+    contract.approve_grant(id);
+    assert!(matches!(
+      contract.execute_grant(id),
+      PromiseOrValue::Promise(_)
+    ));
+    assert_eq!(contract.treasury_balance(&NEAR_TOKEN.to_string()), 0);
+    set_promise_result(PromiseResult::Failed);
+    assert!(!contract.on_grant_transfer(id, 10.0));
+    assert_eq!(
+      contract.get_grant(id).unwrap().status,
+      GrantStatus::Approved
+    );
+    assert_eq!(contract.treasury_balance(&NEAR_TOKEN.to_string()), ONE_NEAR);
+
+    // Campaigns only get grants in their own token, frozen ones none at all
+    usdn_campaign(&mut contract, "wells2");
+    assert_eq!(
+      contract.grant_recipient_ok(
+        &GrantRecipient::Campaign {
+          id: "wells2".to_string(),
+        },
+        NEAR_TOKEN
+      ),
+      Err(ContractError::TokenMismatch)
+    );
+    freeze(
+      &mut contract,
+      FlagTarget::Campaign {
+        id: "wells".to_string(),
+      },
+    );
+    assert_eq!(
+      contract.grant_recipient_ok(&wells, NEAR_TOKEN),
+      Err(ContractError::Frozen)
+    );
+  }
+
+  #[test]
+  fn test_donation_target() {
+    let campaign = Some("wells".to_string());
+    let null = Some("null".to_string());
+    assert_eq!(
+      DonationTarget::from_legacy("campaign", campaign.clone(), null.clone(), None),
+      Some(DonationTarget::Campaign {
+        id: "wells".to_string()
+      })
+    );
+    assert_eq!(
+      DonationTarget::from_legacy("event", campaign.clone(), null.clone(), None),
+      None
+    );
+    assert_eq!(
+      DonationTarget::from_legacy("general", null.clone(), null.clone(), None),
+      Some(DonationTarget::General)
+    );
+    assert_eq!(
+      DonationTarget::from_legacy("cause", None, None, Some("water".to_string())),
+      Some(DonationTarget::Cause {
+        id: "water".to_string()
+      })
+    );
+    assert_eq!(
+      DonationTarget::from_legacy("tree", campaign, null, None),
+      None
+    );
+
+    // Tagged by type in JSON
+    assert_eq!(
+      near_sdk::serde_json::to_value(DonationTarget::Event {
+        id: "gala".to_string()
+      })
+      .unwrap(),
+      json!({ "type": "event", "id": "gala" })
+    );
+    let general: DonationTarget =
+      near_sdk::serde_json::from_value(json!({ "type": "general" })).unwrap();
+    assert_eq!(general, DonationTarget::General);
+    // Unknown types parse, on the ft_transfer_call path as well, and are refused with a coded error
+    let tree: DonationTarget =
+      near_sdk::serde_json::from_value(json!({ "type": "tree", "id": "oak" })).unwrap();
+    assert_eq!(check_target(&tree), Err(ContractError::UnknownTarget));
+    let msg = json!({ "id": "d1", "target": { "type": "tree" }, "amount_usd": 1.0 });
+    assert_eq!(DonationMsg::parse(&msg.to_string()).target, tree);
+    assert_eq!(check_target(&general), Ok(()));
+  }
+
+  #[test]
+  fn test_unversioned_donations() {
+    set_caller(master(), 0, 0);
+    let old = |target: &str, campaign: &str| DonationV0 {
+      id: "d1".to_string(),
+      donor: dalmasonto(),
+      token: NEAR_TOKEN.to_string(),
+      amount: ONE_NEAR,
+      amount_usd: 5.0,
+      target: target.to_string(),
+      event: Some("null".to_string()),
+      campaign: Some(campaign.to_string()),
+      created_at: 7,
+    };
+    // Read back from the bytes the unversioned contract wrote
+    let bytes = old("campaign", "wells").try_to_vec().unwrap();
+    let donation = Donation::from(DonationV0::try_from_slice(&bytes).unwrap());
+    assert_eq!(
+      donation.target,
+      DonationTarget::Campaign {
+        id: "wells".to_string()
+      }
+    );
+    assert_eq!(
+      (
+        donation.amount,
+        donation.net,
+        donation.fee,
+        donation.created_at
+      ),
+      (ONE_NEAR, ONE_NEAR, 0, 7)
+    );
+    assert!(!donation.anonymous && donation.message.is_none());
+
+    // Targets that don't resolve are taken as general donations
+    let donation = Donation::from(old("event", "wells"));
+    assert_eq!(donation.target, DonationTarget::General);
+  }
+
+  #[test]
+  fn test_errors() {
+    assert_eq!(
+      ContractError::CampaignNotFound.message(),
+      ERR20_CAMPAIGN_NOT_FOUND
+    );
+    assert_eq!(ContractError::Frozen.message(), ERR77_FROZEN);
+
+    // Every message starts with its own code
+    let mut codes: Vec<&str> = include_str!("errors.rs")
+      .lines()
+      .filter(|line| line.starts_with("pub const ERR"))
+      .map(|line| line.split('"').nth(1).unwrap().split(':').next().unwrap())
+      .collect();
+    let count = codes.len();
+    codes.sort();
+    codes.dedup();
+    assert_eq!(codes.len(), count);
+
+    // Lookups report what is missing instead of unwrapping
+    let contract = setup();
+    let missing = "missing".to_string();
+    assert_eq!(
+      contract.find_campaign(&missing).err(),
+      Some(ContractError::CampaignNotFound)
+    );
+    assert_eq!(
+      contract.find_event(&missing).err(),
+      Some(ContractError::EventNotFound)
+    );
+    assert_eq!(
+      contract.find_partner(&missing).err(),
+      Some(ContractError::PartnerNotFound)
+    );
+    assert_eq!(
+      contract.find_cause(&missing).err(),
+      Some(ContractError::CauseNotFound)
+    );
+    assert_eq!(
+      contract.find_account(&usdn()).err(),
+      Some(ContractError::AccNotRegistered)
+    );
+    assert_eq!(
+      contract.find_grant(0).err(),
+      Some(ContractError::GrantNotFound)
+    );
+  }
+
+  #[test]
+  fn test_storage_deposit() {
+    set_caller(alice(), ONE_NEAR, 0);
+    let mut contract = new_contract(Vec::new());
+    let min = contract.storage_balance_bounds().min.0;
+    let balance = contract.storage_deposit(None, None);
+    assert_eq!(balance.total.0, ONE_NEAR);
+    assert_eq!(balance.available.0, ONE_NEAR - min);
+
+    // Registering again only refunds
+    let balance = contract.storage_deposit(None, Some(true));
+    assert_eq!(balance.total.0, ONE_NEAR);
+    assert!(contract.storage_balance_of(supercode()).is_none());
+  }
+
+  #[test]
+  fn test_records_charge_storage() {
+    let mut contract = setup();
+    let min = contract.storage_balance_bounds().min.0;
+    let balance = contract.storage_balance_of(supercode()).unwrap();
+    assert_eq!(balance.total.0, ONE_NEAR);
+    assert!(balance.available.0 < ONE_NEAR - min);
+
+    // The donor pays for the donation, the campaign creator doesn't
+    set_caller(dalmasonto(), ONE_NEAR, 0);
+    contract.near_donation(
+      "d1".to_string(),
+      U128(ONE_NEAR),
+      5.0,
+      DonationTarget::Campaign {
+        id: "wells".to_string(),
+      },
+      None,
+      None,
+      None,
+      None,
+    );
+    let donor = contract.storage_balance_of(dalmasonto()).unwrap();
+    assert!(donor.available.0 < ONE_NEAR - min);
+    assert_eq!(
+      contract.storage_balance_of(supercode()).unwrap().available,
+      balance.available
+    );
+
+    // Only the unlocked part can be withdrawn
+    set_caller(supercode(), 1, 0);
+    let withdrawn = contract.storage_withdraw(None);
+    assert_eq!(withdrawn.total.0, ONE_NEAR - balance.available.0);
+    assert_eq!(withdrawn.available.0, 0);
+  }
+
+  #[test]
+  fn test_storage_unregister() {
+    let mut contract = setup();
+    set_caller(alice(), 1, 0);
+    // alice registered the partner, only force lets go of it
+    assert!(contract.storage_unregister(Some(true)));
+    assert!(contract.storage_balance_of(alice()).is_none());
+
+    set_caller(usdn(), ONE_NEAR, 0);
+    contract.storage_deposit(None, None);
+    set_caller(usdn(), 1, 0);
+    assert!(contract.storage_unregister(None));
+    assert!(!contract.storage_unregister(None));
+  }
+
+  #[test]
+  fn test_voters_and_attendees() {
+    let mut contract = setup();
+    for (donor, id) in [(dalmasonto(), "d1"), (alice(), "d2"), (dalmasonto(), "d3")] {
+      set_caller(donor, ONE_NEAR, 0);
+      contract.near_donation(
+        id.to_string(),
+        U128(ONE_NEAR),
+        5.0,
+        DonationTarget::Campaign {
+          id: "wells".to_string(),
+        },
+        None,
+        None,
+        None,
+        None,
+      );
+    }
+    // Donating twice still only adds the donor once
+    let voters = contract.get_campaign_voters("wells".to_string(), 1, 1);
+    assert_eq!(voters.count, 2);
+    assert_eq!(voters.results, vec![dalmasonto()]);
+    assert_eq!(
+      contract
+        .get_campaign_voters("wells".to_string(), 2, 1)
+        .results,
+      vec![alice()]
+    );
+
+    set_caller(supercode(), 0, 0);
+    contract.add_campaign_partner("wells".to_string(), "redcross".to_string());
+    set_caller(alice(), 0, 0);
+    contract.campaign_vote("wells".to_string(), "redcross".to_string());
+    let voters = contract.get_campaign_voters("wells".to_string(), 1, 10);
+    assert_eq!(voters.results, vec![dalmasonto()]);
+    assert_eq!(
+      contract.get_campaign("wells".to_string()).unwrap().partners["redcross"],
+      1
+    );
+
+    set_caller(supercode(), 0, 0);
+    contract.create_event(
+      "gala".to_string(),
+      "Gala".to_string(),
+      "2022-06-01".to_string(),
+      "water".to_string(),
+      "Charity gala".to_string(),
+      U128(10 * ONE_NEAR),
+      NEAR_TOKEN.to_string(),
+      "Nairobi".to_string(),
+      "physical".to_string(),
+      "".to_string(),
+      "".to_string(),
+      "2022,6,1".to_string(),
+      "img".to_string(),
+    );
+    contract.add_event_partner("gala".to_string(), "redcross".to_string());
+    let add = |contract: &mut Contract| {
+      contract.internal_add_event_partner(&"gala".to_string(), "redcross".to_string())
+    };
+    assert_eq!(
+      add(&mut contract),
+      Err(ContractError::PartnerAlreadyCandidate)
+    );
+    set_caller(dalmasonto(), 0, 0);
+    assert_eq!(add(&mut contract), Err(ContractError::NotAllowed));
+    contract.attend_event("gala".to_string());
+    let attendees = contract.get_event_attendees("gala".to_string(), 1, 10);
+    assert_eq!(attendees.count, 1);
+    assert_eq!(attendees.results, vec![dalmasonto()]);
+    assert_eq!(
+      contract.get_event_voters("gala".to_string(), 1, 10).count,
+      0
+    );
+  }
+
+  // One of each key, nested ones for two different records.
+  // The match fails to compile when a variant is added without listing it here.
+  fn storage_keys() -> Vec<Vec<u8>> {
+    let hash = |id: &str| env::sha256(id.as_bytes());
+    let keys = vec![
+      StorageKey::Guardians,
+      StorageKey::Causes,
+      StorageKey::Events,
+      StorageKey::Campaigns,
+      StorageKey::Donations,
+      StorageKey::Tokens,
+      StorageKey::Partners,
+      StorageKey::CausePools,
+      StorageKey::PoolAllocations,
+      StorageKey::Treasury,
+      StorageKey::Grants,
+      StorageKey::Donors,
+      StorageKey::TokenStats,
+      StorageKey::CauseStats,
+      StorageKey::MonthlyStats,
+      StorageKey::Accounts,
+      StorageKey::CampaignVoters,
+      StorageKey::CampaignVoterSet {
+        campaign_hash: hash("wells"),
+      },
+      StorageKey::CampaignVoterSet {
+        campaign_hash: hash("wells2"),
+      },
+      StorageKey::EventVoters,
+      StorageKey::EventVoterSet {
+        event_hash: hash("wells"),
+      },
+      StorageKey::EventVoterSet {
+        event_hash: hash("gala"),
+      },
+      StorageKey::EventAttendees,
+      StorageKey::EventAttendeeSet {
+        event_hash: hash("wells"),
+      },
+      StorageKey::EventAttendeeSet {
+        event_hash: hash("gala"),
+      },
+      StorageKey::StagedCode,
+      StorageKey::AcceptedTokens,
+      StorageKey::FeeTreasury,
+      StorageKey::MatchingPools,
+      StorageKey::Rounds,
+      StorageKey::RoundTallies,
+      StorageKey::RoundContributions,
+      StorageKey::Subscriptions,
+      StorageKey::CampaignFunds,
+      StorageKey::CampaignContributions,
+      StorageKey::MilestoneVoting,
+      StorageKey::MilestoneVotes,
+      StorageKey::MilestoneVoters,
+      StorageKey::Flags,
+      StorageKey::Frozen,
+      StorageKey::CampaignAnonymousDonors,
+      StorageKey::EventAnonymousDonors,
+      StorageKey::TargetMatchingPools,
+      StorageKey::ActiveSubscriptions,
+      StorageKey::CampaignVoted,
+    ];
+    keys
+      .iter()
+      .map(|key| {
+        match key {
+          StorageKey::Guardians
+          | StorageKey::Causes
+          | StorageKey::Events
+          | StorageKey::Campaigns
+          | StorageKey::Donations
+          | StorageKey::Tokens
+          | StorageKey::Partners
+          | StorageKey::CausePools
+          | StorageKey::PoolAllocations
+          | StorageKey::Treasury
+          | StorageKey::Grants
+          | StorageKey::Donors
+          | StorageKey::TokenStats
+          | StorageKey::CauseStats
+          | StorageKey::MonthlyStats
+          | StorageKey::Accounts
+          | StorageKey::CampaignVoters
+          | StorageKey::CampaignVoterSet { .. }
+          | StorageKey::EventVoters
+          | StorageKey::EventVoterSet { .. }
+          | StorageKey::EventAttendees
+          | StorageKey::EventAttendeeSet { .. }
+          | StorageKey::StagedCode
+          | StorageKey::AcceptedTokens
+          | StorageKey::FeeTreasury
+          | StorageKey::MatchingPools
+          | StorageKey::Rounds
+          | StorageKey::RoundTallies
+          | StorageKey::RoundContributions
+          | StorageKey::Subscriptions
+          | StorageKey::CampaignFunds
+          | StorageKey::CampaignContributions
+          | StorageKey::MilestoneVoting
+          | StorageKey::MilestoneVotes
+          | StorageKey::MilestoneVoters
+          | StorageKey::Flags
+          | StorageKey::Frozen
+          | StorageKey::CampaignAnonymousDonors
+          | StorageKey::EventAnonymousDonors
+          | StorageKey::TargetMatchingPools
+          | StorageKey::ActiveSubscriptions
+          | StorageKey::CampaignVoted => {}
+        }
+        key.try_to_vec().unwrap()
+      })
+      .collect()
+  }
+
+  #[test]
+  fn test_storage_keys_dont_collide() {
+    let keys = storage_keys();
+    for (i, a) in keys.iter().enumerate() {
+      for (j, b) in keys.iter().enumerate() {
+        if i != j {
+          // A prefix of another key would read the other collection's entries
+          assert!(
+            !b.starts_with(a),
+            "storage key {:?} is a prefix of {:?}",
+            a,
+            b
+          );
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn test_record_sets_are_separate() {
+    let mut contract = setup();
+    set_caller(dalmasonto(), 0, 0);
+    contract.add_campaign_voter(&"wells".to_string(), &dalmasonto());
+    contract.add_event_voter(&"wells".to_string(), &alice());
+    assert_eq!(
+      contract
+        .get_campaign_voters("wells".to_string(), 1, 10)
+        .results,
+      vec![dalmasonto()]
+    );
+    assert_eq!(
+      contract
+        .get_event_voters("wells".to_string(), 1, 10)
+        .results,
+      vec![alice()]
+    );
+    assert_eq!(
+      contract
+        .get_campaign_voters("wells2".to_string(), 1, 10)
+        .count,
+      0
+    );
+  }
+
+  fn campaign_v0(id: &str, voters: Vec<AccountId>, current: u128) -> CampaignV0 {
+    CampaignV0 {
+      id: id.to_string(),
+      created_by: supercode(),
+      managers: Vec::new(),
+      title: "Wells".to_string(),
+      cause: "Water".to_string(),
+      start_date: "2022-01-01".to_string(),
+      end_date: "2022-12-31".to_string(),
+      description: "Wells for the village".to_string(),
+      target: 10 * ONE_NEAR,
+      current,
+      current_usd: 11.0,
+      token: NEAR_TOKEN.to_string(),
+      voters,
+      partner: None,
+      partners: HashMap::new(),
+      start_year: 2022,
+      start_month: 1,
+      start_day: 1,
+      end_year: 2022,
+      end_month: 12,
+      end_day: 31,
+      created_on: 0,
+      img: String::new(),
+    }
+  }
+
+  fn donation_v0(
+    id: &str,
+    donor: AccountId,
+    token: &str,
+    amount: u128,
+    target: &str,
+  ) -> DonationV0 {
+    DonationV0 {
+      id: id.to_string(),
+      donor,
+      token: token.to_string(),
+      amount,
+      amount_usd: 1.0,
+      target: target.to_string(),
+      event: Some("null".to_string()),
+      campaign: Some("wells".to_string()),
+      created_at: 1_647_302_400_000_000_000,
+    }
+  }
+
+  // Writes the state exactly as the unversioned contract laid it out, with its prefixes
+  #[test]
+  fn test_migrate_from_unversioned() {
+    set_caller(master(), 0, 0);
+    let mut old = ContractV0 {
+      gurdians: UnorderedSet::new(b"g"),
+      running: true,
+      causes: UnorderedSet::new(b"c"),
+      events: UnorderedMap::new(b"e"),
+      campaigns: UnorderedMap::new(b"a"),
+      donations: UnorderedSet::new(b"d"),
+      tokens: UnorderedMap::new(b"t".to_vec()),
+      partners: UnorderedMap::new(b"z".to_vec()),
+      total_usd: 3.0,
+      causes_count: 2,
+      events_count: 1,
+      campaigns_count: 1,
+      donations_count: 3,
+      tokens_count: 2,
+      partners_count: 1,
+    };
+    old.gurdians.insert(&supercode());
+    old.causes.insert(&CauseV0 {
+      title: "Water".to_string(),
+    });
+    // The same cause was counted twice
+    old.causes.insert(&CauseV0 {
+      title: "Water".to_string(),
+    });
+    old.campaigns.insert(
+      &"wells".to_string(),
+      &campaign_v0("wells", vec![dalmasonto()], ONE_NEAR + 1_000),
+    );
+    old.events.insert(
+      &"meetup".to_string(),
+      &EventV0 {
+        id: "meetup".to_string(),
+        title: "Meetup".to_string(),
+        created_by: supercode(),
+        managers: Vec::new(),
+        cause: "Water".to_string(),
+        date: "2022-03-15".to_string(),
+        description: "Meetup".to_string(),
+        target: ONE_NEAR,
+        current: 0,
+        current_usd: 0.0,
+        token: NEAR_TOKEN.to_string(),
+        attendees: vec![alice()],
+        venue: None,
+        event_type: "physical".to_string(),
+        channel: None,
+        channel_url: None,
+        year: 2022,
+        month: 3,
+        day: 15,
+        created_on: 0,
+        voters: vec![dalmasonto()],
+        partner: None,
+        partners: HashMap::new(),
+        img: String::new(),
+      },
+    );
+    let metadata = |address: &str| TokenMetadata {
+      address: address.to_string(),
+      name: "USDN".to_string(),
+      symbol: "USDN".to_string(),
+      icon: String::new(),
+      decimals: 18,
+    };
+    old
+      .tokens
+      .insert(&usdn().to_string(), &metadata("usdn.testnet"));
+    old
+      .tokens
+      .insert(&"Not a token".to_string(), &metadata("Not a token"));
+    old.partners.insert(
+      &"redcross".to_string(),
+      &PartnerV0 {
+        id: "redcross".to_string(),
+        created_by: supercode(),
+        name: "Red Cross".to_string(),
+        description: String::new(),
+        website: String::new(),
+        logo: String::new(),
+        banner: String::new(),
+      },
+    );
+    old.donations.insert(&donation_v0(
+      "d1",
+      dalmasonto(),
+      NEAR_TOKEN,
+      ONE_NEAR,
+      "campaign",
+    ));
+    // Credited to the near campaign whatever its token
+    old.donations.insert(&donation_v0(
+      "d2",
+      supercode(),
+      usdn().as_ref(),
+      1_000,
+      "campaign",
+    ));
+    old
+      .donations
+      .insert(&donation_v0("d3", alice(), NEAR_TOKEN, ONE_NEAR, "general"));
+    env::state_write(&old);
+
+    let mut contract = Contract::migrate();
+    assert_eq!(contract.get_version(), STATE_VERSION);
+    assert_eq!(contract.get_owner(), master());
+    assert_eq!(contract.get_guardians(), vec![supercode()]);
+    assert_eq!(contract.get_config().upgrade_quorum, DEFAULT_UPGRADE_QUORUM);
+    let cause = contract.get_cause("Water".to_string()).unwrap();
+    assert!(cause.active && cause.title == "Water");
+    let campaign = contract.get_campaign("wells".to_string()).unwrap();
+    assert_eq!((campaign.cause, campaign.fees), ("Water".to_string(), 0));
+    assert_eq!(
+      contract
+        .get_campaign_voters("wells".to_string(), 1, 10)
+        .results,
+      vec![dalmasonto()]
+    );
+    assert_eq!(
+      contract
+        .get_event_voters("meetup".to_string(), 1, 10)
+        .results,
+      vec![dalmasonto()]
+    );
+    assert_eq!(
+      contract
+        .get_event_attendees("meetup".to_string(), 1, 10)
+        .results,
+      vec![alice()]
+    );
+    assert!(contract.is_token_accepted(&usdn()));
+    assert!(
+      !contract
+        .get_partner("redcross".to_string())
+        .unwrap()
+        .verified
+    );
+    let stats = contract.get_donations_stats();
+    assert_eq!(
+      (
+        stats.causes,
+        stats.campaigns,
+        stats.events,
+        stats.tokens,
+        stats.partners
+      ),
+      (1, 1, 1, 1, 1)
+    );
+    assert_eq!((stats.donations, stats.total_usd), (0, 3.0));
+
+    // Nothing is left under the old prefixes but the donations
+    assert!(!old.gurdians.contains(&supercode()));
+    assert!(old.campaigns.get(&"wells".to_string()).is_none());
+    assert!(old.tokens.get(&usdn().to_string()).is_none());
+    assert!(old.partners.get(&"redcross".to_string()).is_none());
+
+    assert_eq!(contract.migrate_donations(2), 1);
+    assert_eq!(contract.migrate_donations(2), 0);
+    assert_eq!(contract.migrate_donations(2), 0);
+    assert!(contract.legacy_donations.is_none());
+    let stats = contract.get_donations_stats();
+    assert_eq!(
+      (stats.donations, stats.donors, stats.total_usd),
+      (3, 3, 3.0)
+    );
+    assert_eq!(
+      contract
+        .get_donation("d2".to_string())
+        .unwrap()
+        .donation
+        .target,
+      DonationTarget::Campaign {
+        id: "wells".to_string()
+      }
+    );
+    let causes = contract.get_cause_stats();
+    assert_eq!(
+      (causes.len(), causes[0].key.as_str(), causes[0].donations),
+      (1, "Water", 2)
+    );
+
+    // Only donations in the campaign's token count for it, the rest went to the treasury
+    let campaign = contract.get_campaign("wells".to_string()).unwrap();
+    assert_eq!((campaign.current, campaign.current_usd), (ONE_NEAR, 10.0));
+    assert_eq!(
+      contract
+        .get_campaign_contribution("wells".to_string(), dalmasonto())
+        .0,
+      ONE_NEAR
+    );
+    assert_eq!(
+      contract
+        .get_campaign_contribution("wells".to_string(), supercode())
+        .0,
+      0
+    );
+    let mut treasury = contract.get_treasury();
+    treasury.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(
+      treasury
+        .iter()
+        .map(|(token, balance)| (token.clone(), balance.amount.0))
+        .collect::<Vec<_>>(),
+      vec![
+        (NEAR_TOKEN.to_string(), ONE_NEAR),
+        (usdn().to_string(), 1_000)
+      ]
+    );
+  }
+
+  // Upgrades that keep the layout call migrate as well
+  #[test]
+  fn test_migrate_keeps_current_state() {
+    set_caller(master(), 0, 0);
+    let mut contract = new_contract(vec![master()]);
+    contract.create_cause(
+      "water".to_string(),
+      "Water".to_string(),
+      "Clean water".to_string(),
+      "icon".to_string(),
+    );
+    env::state_write(&contract);
+
+    let contract = Contract::migrate();
+    assert_eq!(contract.get_guardians(), vec![master()]);
+    assert_eq!(contract.get_donations_stats().causes, 1);
+    assert!(contract.get_cause("water".to_string()).is_some());
+  }
+
+  #[test]
+  fn test_new_state_is_current_version() {
+    set_caller(master(), 0, 0);
+    assert_eq!(new_contract(Vec::new()).get_version(), STATE_VERSION);
+  }
+
+  fn stage(contract: &mut Contract, code: &[u8]) {
+    let mut context = VMContextBuilder::new()
+      .current_account_id(master())
+      .predecessor_account_id(master())
+      .build();
+    context.input = code.to_vec();
+    testing_env!(context);
+    contract.stage_upgrade();
+  }
+
+  #[test]
+  fn test_staged_upgrade() {
+    let mut contract = setup();
+    set_caller(master(), 0, 0);
+    contract.add_guardian(supercode());
+    contract.set_upgrade_quorum(2);
+
+    stage(&mut contract, b"old wasm");
+    stage(&mut contract, b"new wasm");
+    let staged = contract.get_staged_upgrade().unwrap();
+    let hash: CryptoHash = env::sha256(b"new wasm").try_into().unwrap();
+    assert_eq!(staged.hash, Base58CryptoHash::from(hash));
+    assert_eq!(staged.size, 8);
+    assert_eq!(staged.staged_by, master());
+    assert!(staged.approvals.is_empty());
+
+    set_caller(master(), 0, 0);
+    contract.approve_upgrade(staged.hash);
+    set_caller(supercode(), 0, 0);
+    contract.approve_upgrade(staged.hash);
+    assert_eq!(
+      contract.get_staged_upgrade().unwrap().approvals,
+      vec![master(), supercode()]
+    );
+
+    contract.upgrade();
+    assert!(contract.get_staged_upgrade().is_none());
+    assert!(contract.staged_code.get().is_none());
+
+    // What upgrade keeps for itself has to be left over after the call so far
+    assert_eq!(
+      migrate_gas(GAS_FOR_UPGRADE, Gas(1)),
+      Err(ContractError::UpgradeGas)
+    );
+    assert_eq!(migrate_gas(Gas(GAS_FOR_UPGRADE.0 + 10), Gas(4)), Ok(Gas(6)));
+  }
+
+  #[test]
+  fn test_cancel_upgrade() {
+    let mut contract = setup();
+    stage(&mut contract, b"new wasm");
+    set_caller(master(), 0, 0);
+    contract.cancel_upgrade();
+    assert!(contract.get_staged_upgrade().is_none());
+    assert!(contract.staged_code.get().is_none());
+  }
+
+  #[test]
+  fn test_quorum() {
+    assert_eq!(check_quorum(0), Err(ContractError::InvalidQuorum));
+    assert_eq!(check_quorum(1), Ok(()));
+
+    let mut contract = setup();
+    set_caller(master(), 0, 0);
+    contract.set_upgrade_quorum(1);
+    contract.set_grant_quorum(3);
+    assert_eq!(
+      (
+        contract.get_config().upgrade_quorum,
+        contract.get_config().grant_quorum
+      ),
+      (1, 3)
+    );
+  }
+
+  #[test]
+  fn test_init_args() {
+    set_caller(alice(), 0, 0);
+    let token = TokenMetadata {
+      address: usdn().to_string(),
+      name: "USN".to_string(),
+      symbol: "USN".to_string(),
+      icon: "".to_string(),
+      decimals: 18,
+    };
+    let contract = Contract::new(
+      dalmasonto(),
+      vec![master(), supercode()],
+      vec![token],
+      Config {
+        fee_bps: 250,
+        grant_quorum: 2,
+        upgrade_quorum: 2,
+        milestone_quorum_bps: 2_000,
+        milestone_threshold_bps: 5_000,
+        milestone_vote_period: MIN_MILESTONE_VOTE_PERIOD,
+        partner_voting: PartnerVoting::Weighted,
+      },
+    );
+    assert_eq!(contract.get_owner(), dalmasonto());
+    assert_eq!(contract.get_guardians(), vec![master(), supercode()]);
+    let token = contract.get_token(usdn()).unwrap();
+    assert_eq!(token.metadata.unwrap().symbol, "USN");
+    assert!(contract.is_token_accepted(&usdn()));
+    assert_eq!(contract.get_donations_stats().tokens, 1);
+    assert_eq!(contract.get_config().fee_bps, 250);
+    assert_eq!(
+      contract.get_config().partner_voting,
+      PartnerVoting::Weighted
+    );
+
+    assert_eq!(token_account("usdn.testnet"), Ok(usdn()));
+    assert_eq!(
+      token_account("Not a token"),
+      Err(ContractError::InvalidTokenAddress)
+    );
+  }
+
+  #[test]
+  fn test_weighted_partner_votes() {
+    let mut contract = setup();
+    set_caller(master(), 0, 0);
+    contract.set_partner_voting(PartnerVoting::Weighted);
+    set_caller(supercode(), 0, 0);
+    contract.add_campaign_partner("wells".to_string(), "redcross".to_string());
+
+    // Whole tokens count, the rest is dropped
+    donate(&mut contract, "d1", 3 * ONE_NEAR + 500);
+    contract.campaign_vote("wells".to_string(), "redcross".to_string());
+    // Donors of less than a token still get a vote
+    set_caller(alice(), 10, 0);
+    contract.near_donation(
+      "d2".to_string(),
+      U128(10),
+      0.0,
+      DonationTarget::Campaign {
+        id: "wells".to_string(),
+      },
+      None,
+      None,
+      None,
+      None,
+    );
+    contract.campaign_vote("wells".to_string(), "redcross".to_string());
+    assert_eq!(
+      contract.get_campaign("wells".to_string()).unwrap().partners["redcross"],
+      4
+    );
+
+    // Donating again doesn't give another vote
+    donate(&mut contract, "d3", 1);
+    assert!(contract
+      .get_campaign_voters("wells".to_string(), 1, 10)
+      .results
+      .is_empty());
+    assert_eq!(
+      contract.internal_campaign_vote(&"wells".to_string(), "redcross".to_string()),
+      Err(ContractError::AlreadyVoted)
+    );
+    assert_eq!(
+      contract.get_campaign("wells".to_string()).unwrap().partners["redcross"],
+      4
+    );
+  }
+
+  #[test]
+  fn test_owner_manages_guardians() {
+    let mut contract = setup();
+    set_caller(master(), 0, 0);
+    contract.add_guardian(supercode());
+    contract.set_owner(dalmasonto());
+    set_caller(dalmasonto(), 0, 0);
+    contract.remove_guardian(master());
+    assert_eq!(contract.get_guardians(), vec![supercode()]);
+  }
+
+  fn set_promise_result(result: PromiseResult) {
+    set_promise_results(vec![result]);
+  }
+
+  fn set_promise_results(results: Vec<PromiseResult>) {
+    testing_env!(
+      VMContextBuilder::new()
+        .current_account_id(master())
+        .predecessor_account_id(master())
+        .build(),
+      near_sdk::VMConfig::test(),
+      near_sdk::RuntimeFeesConfig::test(),
+      Default::default(),
+      results,
+    );
+  }
+
+  #[test]
+  fn test_token_whitelist() {
+    let mut contract = setup();
+    set_caller(master(), 0, 0);
+    contract.add_token(usdn(), None);
+    let token = contract.get_token(usdn()).unwrap();
+    assert!(token.metadata.is_none());
+    assert!(contract.is_token_accepted(&usdn()));
+
+    // ft_metadata answered by the token contract
+    let metadata = json!({
+      "spec": "ft-1.0.0",
+      "name": "USN",
+      "symbol": "USN",
+      "icon": null,
+      "reference": null,
+      "reference_hash": null,
+      "decimals": 18,
+    });
+    set_promise_result(PromiseResult::Successful(metadata.to_string().into_bytes()));
+    assert!(contract.on_ft_metadata(usdn()));
+    let token = contract.get_token(usdn()).unwrap().metadata.unwrap();
+    assert_eq!(token.address, usdn().to_string());
+    assert_eq!(token.decimals, 18);
+
+    set_caller(master(), 0, 0);
+    contract.set_token_enabled(usdn(), false);
+    assert!(!contract.is_token_accepted(&usdn()));
+    // Metadata of another token is refused
+    let other = TokenMetadata {
+      address: "other.testnet".to_string(),
+      ..token.clone()
+    };
+    assert_eq!(
+      check_token_metadata(&usdn(), &Some(other)),
+      Err(ContractError::TokenAddressMismatch)
+    );
+    assert_eq!(check_token_metadata(&usdn(), &None), Ok(()));
+    // Adding again keeps it disabled and isn't counted twice
+    contract.add_token(usdn(), Some(token));
+    assert!(!contract.is_token_accepted(&usdn()));
+    assert_eq!(contract.get_donations_stats().tokens, 1);
+
+    contract.remove_token(usdn());
+    assert!(contract.get_token(usdn()).is_none());
+    assert_eq!(contract.get_donations_stats().tokens, 0);
+  }
+
+  #[test]
+  fn test_payout_registers_receiver() {
+    let mut contract = setup();
+    set_caller(master(), 0, 0);
+    contract.set_ft_storage_budget(U128(FT_STORAGE_DEPOSIT));
+
+    // Registered receivers are paid straight away
+    set_promise_result(PromiseResult::Successful(
+      json!({"total": "1", "available": "0"})
+        .to_string()
+        .into_bytes(),
+    ));
+    contract.on_payout_storage_checked(usdn(), dalmasonto(), U128(10));
+    assert_eq!(contract.get_ft_storage_budget().0, FT_STORAGE_DEPOSIT);
+
+    // Unregistered ones are registered from the budget
+    set_promise_result(PromiseResult::Successful(b"null".to_vec()));
+    contract.on_payout_storage_checked(usdn(), dalmasonto(), U128(10));
+    assert_eq!(contract.get_ft_storage_budget().0, 0);
+
+    // A failed registration gives the deposit back to the budget
+    let unregistered = || PromiseResult::Successful(b"null".to_vec());
+    set_promise_results(vec![unregistered(), PromiseResult::Failed]);
+    let registered = contract.on_payout_registered(usdn(), dalmasonto(), U128(10));
+    assert!(matches!(registered, PromiseOrValue::Value(false)));
+    assert_eq!(contract.get_ft_storage_budget().0, FT_STORAGE_DEPOSIT);
+
+    // What the token did not need is refunded, and goes back to the budget
+    let balance = || {
+      PromiseResult::Successful(
+        json!({"total": (FT_STORAGE_DEPOSIT - 1_000).to_string(), "available": "0"})
+          .to_string()
+          .into_bytes(),
+      )
+    };
+    set_promise_result(unregistered());
+    contract.on_payout_storage_checked(usdn(), dalmasonto(), U128(10));
+    set_promise_results(vec![unregistered(), balance()]);
+    let registered = contract.on_payout_registered(usdn(), dalmasonto(), U128(10));
+    assert!(matches!(registered, PromiseOrValue::Promise(_)));
+    assert_eq!(contract.get_ft_storage_budget().0, 1_000);
+
+    // A receiver registered in the meantime gets nothing, the whole deposit is refunded
+    set_caller(master(), 0, 0);
+    contract.set_ft_storage_budget(U128(FT_STORAGE_DEPOSIT));
+    set_promise_result(unregistered());
+    contract.on_payout_storage_checked(usdn(), dalmasonto(), U128(10));
+    set_promise_results(vec![balance(), balance()]);
+    contract.on_payout_registered(usdn(), dalmasonto(), U128(10));
+    assert_eq!(contract.get_ft_storage_budget().0, FT_STORAGE_DEPOSIT);
+
+    // Without enough budget the payout fails before anything is spent
+    set_caller(master(), 0, 0);
+    contract.set_ft_storage_budget(U128(0));
+    set_promise_result(PromiseResult::Successful(b"null".to_vec()));
+    let checked = contract.on_payout_storage_checked(usdn(), dalmasonto(), U128(10));
+    assert!(matches!(checked, PromiseOrValue::Value(false)));
+
+    set_promise_result(PromiseResult::Failed);
+    assert!(!contract.on_payout_transferred());
+    set_promise_result(PromiseResult::Successful(b"false".to_vec()));
+    assert!(!Contract::payout_succeeded());
+    set_promise_result(PromiseResult::Successful(Vec::new()));
+    assert!(Contract::payout_succeeded());
+  }
+
+  fn donate(contract: &mut Contract, id: &str, amount: u128) {
+    set_caller(dalmasonto(), amount, 0);
+    contract.near_donation(
+      id.to_string(),
+      U128(amount),
+      10.0,
+      DonationTarget::Campaign {
+        id: "wells".to_string(),
+      },
+      None,
+      None,
+      None,
+      None,
+    );
+  }
+
+  #[test]
+  fn test_platform_fee() {
+    let mut contract = setup();
+    set_caller(master(), 0, 0);
+    contract.set_fee_bps(250);
+
+    donate(&mut contract, "d1", 1000);
+    let donation = contract.get_donation("d1".to_string()).unwrap().donation;
+    assert_eq!(
+      (donation.amount, donation.fee, donation.net),
+      (1000, 25, 975)
+    );
+    let campaign = contract.get_campaign("wells".to_string()).unwrap();
+    assert_eq!((campaign.current, campaign.fees), (975, 25));
+    assert_eq!(campaign.current_usd, 9.75);
+    // Donor totals stay gross
+    assert_eq!(contract.get_donations_stats().total_usd, 10.0);
+
+    // The campaign override wins, zero included
+    set_caller(master(), 0, 0);
+    contract.set_campaign_fee("wells".to_string(), Some(0));
+    donate(&mut contract, "d2", 1000);
+    let campaign = contract.get_campaign("wells".to_string()).unwrap();
+    assert_eq!((campaign.current, campaign.fees), (1975, 25));
+
+    assert_eq!(
+      contract.get_fee_treasury(),
+      vec![(NEAR_TOKEN.to_string(), U128(25))]
+    );
+    set_caller(master(), 0, 0);
+    contract.withdraw_fees(NEAR_TOKEN.to_string(), Some(U128(10)));
+    assert_eq!(
+      contract.get_fee_treasury(),
+      vec![(NEAR_TOKEN.to_string(), U128(15))]
+    );
+    // A failed withdrawal puts the fees back
+    set_promise_result(PromiseResult::Failed);
+    assert!(!contract.on_fees_withdrawn(NEAR_TOKEN.to_string(), U128(10)));
+    assert_eq!(
+      contract.get_fee_treasury(),
+      vec![(NEAR_TOKEN.to_string(), U128(25))]
+    );
+    set_promise_result(PromiseResult::Successful(Vec::new()));
+    assert!(contract.on_fees_withdrawn(NEAR_TOKEN.to_string(), U128(10)));
+    let logs = event_logs();
+    assert_eq!(logs[0]["event"], "payout_sent");
+    assert_eq!(logs[0]["data"][0]["receiver_id"], master().to_string());
+    assert_eq!(logs[0]["data"][0]["memo"], "fees:near");
+  }
+
+  #[test]
+  fn test_donation_tips() {
+    let mut contract = setup();
+    set_caller(dalmasonto(), 1100, 0);
+    contract.near_donation(
+      "d1".to_string(),
+      U128(1000),
+      10.0,
+      DonationTarget::Campaign {
+        id: "wells".to_string(),
+      },
+      None,
+      None,
+      None,
+      Some(U128(100)),
+    );
+    let donation = contract.get_donation("d1".to_string()).unwrap().donation;
+    assert_eq!(
+      (donation.amount, donation.net, donation.tip),
+      (1000, 1000, 100)
+    );
+    assert_eq!(
+      contract.get_campaign("wells".to_string()).unwrap().current,
+      1000
+    );
+
+    let stats = contract.get_donations_stats();
+    assert_eq!(stats.tips, 1);
+    assert_eq!(stats.tips_usd, 1.0);
+    assert_eq!(stats.total_usd, 10.0);
+    assert_eq!(
+      contract.get_fee_treasury(),
+      vec![(NEAR_TOKEN.to_string(), U128(100))]
+    );
+
+    // Token donations carry the tip in the msg
+    let msg = json!({
+      "id": "d2",
+      "target": { "type": "general" },
+      "amount_usd": 20.0,
+      "tip": "50",
+    });
+    assert_eq!(DonationMsg::parse(&msg.to_string()).tip, Some(U128(50)));
+  }
+
+  #[test]
+  fn test_matching_pool() {
+    let mut contract = setup();
+    set_caller(alice(), 8 * ONE_NEAR, 0);
+    let terms = MatchingTerms {
+      target: campaign("wells"),
+      ratio_bps: ONE_TO_ONE_BPS,
+      cap: Some(U128(3 * ONE_NEAR)),
+    };
+    assert_eq!(contract.create_matching_pool(terms), 0);
+
+    let used = contract.accounts.get(&alice()).unwrap().storage_used;
+    donate(&mut contract, "d1", 2 * ONE_NEAR);
+    let matched = contract
+      .get_donation("d1:match:0".to_string())
+      .unwrap()
+      .donation;
+    assert_eq!((matched.donor, matched.amount), (alice(), 2 * ONE_NEAR));
+    // The sponsor pays for the match
+    let match_storage = contract.accounts.get(&alice()).unwrap().storage_used - used;
+    assert!(match_storage > 0 && match_storage <= MATCH_DONATION_STORAGE);
+    // Capped per donation
+    donate(&mut contract, "d2", 5 * ONE_NEAR);
+    let pool = contract.get_matching_pool(0).unwrap();
+    assert_eq!((pool.balance.0, pool.matched), (3 * ONE_NEAR, 2));
+    assert_eq!(
+      contract.get_campaign("wells".to_string()).unwrap().current,
+      12 * ONE_NEAR
+    );
+
+    // The rest goes back once the campaign ended, and the pool leaves the index until the refund went through
+    set_caller(alice(), 0, 1_672_531_200_000_000_000);
+    contract.refund_matching_pool(0);
+    assert_eq!(contract.get_matching_pool(0).unwrap().balance.0, 0);
+    assert!(contract
+      .get_target_matching_pools(campaign("wells"))
+      .is_empty());
+    set_promise_result(PromiseResult::Failed);
+    assert!(!contract.on_matching_refund(0, U128(3 * ONE_NEAR)));
+    assert_eq!(
+      contract.get_matching_pool(0).unwrap().balance.0,
+      3 * ONE_NEAR
+    );
+    assert_eq!(
+      contract.get_target_matching_pools(campaign("wells")).len(),
+      1
+    );
+  }
+
+  #[test]
+  fn test_matching_pool_limits() {
+    let mut contract = setup();
+    let terms = MatchingTerms {
+      target: campaign("wells"),
+      ratio_bps: ONE_TO_ONE_BPS,
+      cap: None,
+    };
+    assert_eq!(
+      contract.check_matching_pool(NEAR_TOKEN, ONE_NEAR - 1, &terms),
+      Err(ContractError::MatchingPoolTooSmall)
+    );
+    for _ in 0..MAX_MATCHING_POOLS_PER_TARGET {
+      set_caller(alice(), ONE_NEAR, 0);
+      contract.create_matching_pool(terms.clone());
+    }
+    assert_eq!(
+      contract.check_matching_pool(NEAR_TOKEN, ONE_NEAR, &terms),
+      Err(ContractError::MatchingPoolLimit)
+    );
+    assert_eq!(
+      check_donation_id("d1:match:0"),
+      Err(ContractError::ReservedDonationId)
+    );
+  }
+
+  #[test]
+  fn test_matching_pool_runs_dry() {
+    let mut contract = setup();
+    let terms = MatchingTerms {
+      target: campaign("wells"),
+      ratio_bps: ONE_TO_ONE_BPS,
+      cap: None,
+    };
+    set_caller(alice(), ONE_NEAR, 0);
+    contract.create_matching_pool(terms.clone());
+    set_caller(supercode(), ONE_NEAR, 0);
+    contract.create_matching_pool(terms);
+    // A sponsor without storage left is skipped rather than failing the donation
+    set_caller(supercode(), 1, 0);
+    contract.storage_withdraw(None);
+
+    donate(&mut contract, "d1", 2 * ONE_NEAR);
+    assert!(contract.get_donation("d1:match:0".to_string()).is_some());
+    assert!(contract.get_donation("d1:match:1".to_string()).is_none());
+    assert_eq!(contract.get_matching_pool(1).unwrap().balance.0, ONE_NEAR);
+    // The emptied pool leaves the index
+    let pools = contract.get_target_matching_pools(campaign("wells"));
+    assert_eq!(
+      pools.iter().map(|pool| pool.id).collect::<Vec<u64>>(),
+      vec![1]
+    );
+  }
+
+  fn donate_to(
+    contract: &mut Contract,
+    donor: AccountId,
+    target: DonationTarget,
+    amount: u128,
+    timestamp: u64,
+  ) {
+    set_caller(donor, amount, timestamp);
+    let id = format!("d{}", contract.get_donations_stats().donations);
+    contract.near_donation(id, U128(amount), 1.0, target, None, None, None, None);
+  }
+
+  fn campaign(id: &str) -> DonationTarget {
+    DonationTarget::Campaign { id: id.to_string() }
+  }
+
+  #[test]
+  fn test_quadratic_round() {
+    let mut contract = setup();
+    set_caller(supercode(), 0, 0);
+    contract.create_campaign(
+      "roads".to_string(),
+      "Roads".to_string(),
+      "water".to_string(),
+      "Build roads".to_string(),
+      U128(100 * ONE_NEAR),
+      NEAR_TOKEN.to_string(),
+      "2022-01-01".to_string(),
+      "2022,1,1".to_string(),
+      "2022-12-31".to_string(),
+      "2022,12,31".to_string(),
+      "img".to_string(),
+    );
+    donate_to(
+      &mut contract,
+      dalmasonto(),
+      DonationTarget::General,
+      1000,
+      0,
+    );
+    set_caller(master(), 0, 0);
+    let id = contract.create_round(
+      "Water".to_string(),
+      NEAR_TOKEN.to_string(),
+      U128(1000),
+      10,
+      100,
+      vec![
+        "wells".to_string(),
+        "roads".to_string(),
+        "wells".to_string(),
+      ],
+    );
+    assert_eq!(contract.get_round(id).unwrap().campaigns.len(), 2);
+
+    // Before the round starts
+    donate_to(&mut contract, alice(), campaign("wells"), 900, 5);
+    donate_to(&mut contract, alice(), campaign("wells"), 100, 10);
+    donate_to(&mut contract, dalmasonto(), campaign("wells"), 100, 20);
+    donate_to(&mut contract, supercode(), campaign("roads"), 300, 30);
+    donate_to(&mut contract, supercode(), campaign("roads"), 100, 40);
+    donate_to(&mut contract, alice(), campaign("roads"), 100, 50);
+    assert_eq!(
+      contract
+        .get_round_contribution(id, "roads".to_string(), supercode())
+        .0,
+      400
+    );
+    let tally = contract.get_round_tally(id, "wells".to_string());
+    assert_eq!((tally.contributed.0, tally.donors), (200, 2));
+    // (10 + 10)^2 - 200 and (20 + 10)^2 - 500
+    assert_eq!(tally.score(), 200.0);
+    assert_eq!(
+      contract.get_round_tally(id, "roads".to_string()).score(),
+      400.0
+    );
+
+    assert_eq!(contract.get_round(id).unwrap().status, RoundStatus::Open);
+    set_caller(master(), 0, 100);
+    assert_eq!(contract.finalise_round(id, 1), RoundStatus::Tallying);
+    // Closed rounds no longer count donations
+    donate_to(&mut contract, alice(), campaign("roads"), 100, 100);
+    set_caller(master(), 0, 100);
+    assert_eq!(contract.finalise_round(id, 1), RoundStatus::Paying);
+    assert_eq!(contract.finalise_round(id, 5), RoundStatus::Finalised);
+
+    let wells = contract.get_round_tally(id, "wells".to_string());
+    let roads = contract.get_round_tally(id, "roads".to_string());
+    assert_eq!((wells.matched.0, roads.matched.0), (333, 666));
+    assert_eq!(
+      contract.get_campaign("wells".to_string()).unwrap().current,
+      900 + 100 + 100 + 333
+    );
+    let round = contract.get_round(id).unwrap();
+    assert_eq!(round.distributed.0, 999);
+    assert_eq!(contract.treasury_balance(&NEAR_TOKEN.to_string()), 1);
+  }
+
+  #[test]
+  fn test_round_campaigns() {
+    let mut contract = setup();
+    usdn_campaign(&mut contract, "usdn-wells");
+    assert_eq!(
+      contract.round_campaigns(
+        NEAR_TOKEN,
+        vec!["wells".to_string(), "usdn-wells".to_string()]
+      ),
+      Err(ContractError::TokenMismatch)
+    );
+    assert_eq!(
+      contract.round_campaigns(usdn().as_str(), vec!["usdn-wells".to_string()]),
+      Ok(vec!["usdn-wells".to_string()])
+    );
+  }
+
+  #[test]
+  fn test_round_skips_frozen_campaign() {
+    let mut contract = setup();
+    donate_to(
+      &mut contract,
+      dalmasonto(),
+      DonationTarget::General,
+      1000,
+      0,
+    );
+    set_caller(master(), 0, 0);
+    let id = contract.create_round(
+      "Water".to_string(),
+      NEAR_TOKEN.to_string(),
+      U128(1000),
+      10,
+      100,
+      vec!["wells".to_string()],
+    );
+    donate_to(&mut contract, alice(), campaign("wells"), 100, 10);
+    donate_to(&mut contract, dalmasonto(), campaign("wells"), 100, 20);
+    assert_eq!(
+      contract.get_round_tally(id, "wells".to_string()).score(),
+      200.0
+    );
+
+    // The frozen campaign's share goes back to the treasury
+    freeze(
+      &mut contract,
+      FlagTarget::Campaign {
+        id: "wells".to_string(),
+      },
+    );
+    set_caller(master(), 0, 100);
+    assert_eq!(contract.finalise_round(id, 5), RoundStatus::Paying);
+    assert_eq!(contract.finalise_round(id, 5), RoundStatus::Finalised);
+    assert_eq!(
+      contract.get_round_tally(id, "wells".to_string()).matched.0,
+      0
+    );
+    assert_eq!(contract.get_round(id).unwrap().distributed.0, 0);
+    assert_eq!(contract.treasury_balance(&NEAR_TOKEN.to_string()), 1000);
+  }
+
+  #[test]
+  fn test_subscription() {
+    let mut contract = setup();
+    let terms = SubscriptionTerms {
+      target: campaign("wells"),
+      amount: U128(100),
+      amount_usd: 1.0,
+      interval: MIN_SUBSCRIPTION_INTERVAL,
+      anonymous: None,
+    };
+    set_caller(alice(), 250, 0);
+    let id = contract.create_subscription(terms.clone());
+
+    // Due right away, then once every interval
+    set_caller(master(), 0, 0);
+    assert_eq!(contract.process_due_subscriptions(10), 1);
+    assert_eq!(contract.process_due_subscriptions(10), 0);
+    let donation = contract
+      .get_donation("sub:0:0".to_string())
+      .unwrap()
+      .donation;
+    assert_eq!((donation.donor, donation.amount), (alice(), 100));
+    set_caller(master(), 0, MIN_SUBSCRIPTION_INTERVAL);
+    assert_eq!(contract.process_due_subscriptions(10), 1);
+
+    // 50 left is not enough for another donation
+    let subscription = contract.get_subscription(id).unwrap();
+    assert_eq!((subscription.balance.0, subscription.donations), (50, 2));
+    assert!(!subscription.active);
+    assert_eq!(
+      contract.get_campaign("wells".to_string()).unwrap().current,
+      200
+    );
+    set_caller(alice(), 0, MIN_SUBSCRIPTION_INTERVAL);
+    contract.withdraw_subscription(id);
+    assert_eq!(contract.get_subscription(id).unwrap().balance.0, 0);
+    set_promise_result(PromiseResult::Failed);
+    assert!(!contract.on_subscription_withdrawn(id, U128(50)));
+    assert_eq!(contract.get_subscription(id).unwrap().balance.0, 50);
+
+    // Cancelled subscriptions are skipped, ended campaigns stop them
+    set_caller(supercode(), 1000, 0);
+    let cancelled = contract.create_subscription(terms.clone());
+    let ended = contract.create_subscription(terms);
+    contract.cancel_subscription(cancelled);
+    set_caller(master(), 0, 1_672_531_200_000_000_000);
+    assert_eq!(contract.process_due_subscriptions(10), 0);
+    assert!(!contract.get_subscription(ended).unwrap().active);
+    assert_eq!(
+      contract.get_donor_subscriptions(supercode(), 1, 10).count,
+      2
+    );
+
+    // Token subscriptions come in through ft_transfer_call
+    let msg = json!({
+      "subscription": {
+        "target": { "type": "general" },
+        "amount": "10",
+        "amount_usd": 10.0,
+        "interval": MIN_SUBSCRIPTION_INTERVAL,
+      }
+    });
+    let parsed: SubscriptionMsg = near_sdk::serde_json::from_str(&msg.to_string()).unwrap();
+    assert_eq!(parsed.subscription.amount, U128(10));
+  }
+
+  #[test]
+  fn test_subscription_cursor() {
+    let mut contract = setup();
+    let terms = SubscriptionTerms {
+      target: campaign("wells"),
+      amount: U128(100),
+      amount_usd: 1.0,
+      interval: MIN_SUBSCRIPTION_INTERVAL,
+      anonymous: None,
+    };
+    set_caller(alice(), 250, 0);
+    for _ in 0..3 {
+      contract.create_subscription(terms.clone());
+    }
+    // Each call picks up where the last one stopped
+    set_caller(master(), 0, 0);
+    assert_eq!(contract.process_due_subscriptions(2), 2);
+    assert_eq!(contract.process_due_subscriptions(2), 1);
+    assert_eq!(contract.get_subscription(2).unwrap().donations, 1);
+    assert_eq!(contract.process_due_subscriptions(10), 0);
+
+    // Cancelled and emptied subscriptions leave the index
+    set_caller(alice(), 0, 0);
+    contract.cancel_subscription(0);
+    assert_eq!(contract.active_subscriptions.len(), 2);
+    set_caller(master(), 0, MIN_SUBSCRIPTION_INTERVAL);
+    assert_eq!(contract.process_due_subscriptions(10), 2);
+    assert!(contract.active_subscriptions.is_empty());
+    assert_eq!(
+      check_donation_id("sub:0:0"),
+      Err(ContractError::ReservedDonationId)
+    );
+  }
+
+  #[test]
+  fn test_subscription_donation_storage() {
+    let mut contract = setup();
+    donate_to(
+      &mut contract,
+      dalmasonto(),
+      DonationTarget::General,
+      1000,
+      0,
+    );
+    set_caller(master(), 0, 0);
+    contract.create_round(
+      "Water".to_string(),
+      NEAR_TOKEN.to_string(),
+      U128(1000),
+      0,
+      100,
+      vec!["wells".to_string()],
+    );
+    set_caller(supercode(), ONE_NEAR, 0);
+    contract.create_matching_pool(MatchingTerms {
+      target: campaign("wells"),
+      ratio_bps: ONE_TO_ONE_BPS,
+      cap: None,
+    });
+    set_caller(alice(), 100, 0);
+    contract.create_subscription(SubscriptionTerms {
+      target: campaign("wells"),
+      amount: U128(100),
+      amount_usd: 1.0,
+      interval: MIN_SUBSCRIPTION_INTERVAL,
+      anonymous: None,
+    });
+
+    // The donor pays for the donation and its round contribution, the sponsor for the match
+    let used = contract.accounts.get(&alice()).unwrap().storage_used;
+    set_caller(master(), 0, 0);
+    assert_eq!(contract.process_due_subscriptions(10), 1);
+    assert!(contract
+      .get_donation("sub:0:0:match:0".to_string())
+      .is_some());
+    assert_eq!(
+      contract
+        .get_round_contribution(0, "wells".to_string(), alice())
+        .0,
+      100
+    );
+    let donation_storage = contract.accounts.get(&alice()).unwrap().storage_used - used;
+    assert!(donation_storage <= SUBSCRIPTION_DONATION_STORAGE);
+  }
+
+  #[test]
+  fn test_subscription_donation_storage_rounds() {
+    let mut contract = setup();
+    donate_to(
+      &mut contract,
+      dalmasonto(),
+      DonationTarget::General,
+      1000,
+      0,
+    );
+    // Enough open rounds for a donation to outgrow the flat estimate
+    set_caller(master(), 0, 0);
+    for _ in 0..10 {
+      contract.create_round(
+        "Water".to_string(),
+        NEAR_TOKEN.to_string(),
+        U128(10),
+        0,
+        100,
+        vec!["wells".to_string()],
+      );
+    }
+    let terms = SubscriptionTerms {
+      target: campaign("wells"),
+      amount: U128(100),
+      amount_usd: 1.0,
+      interval: MIN_SUBSCRIPTION_INTERVAL,
+      anonymous: None,
+    };
+    let donors: Vec<AccountId> = ["a", "b", "c"]
+      .iter()
+      .map(|name| {
+        format!("{}{}.testnet", name, "x".repeat(55))
+          .parse()
+          .unwrap()
+      })
+      .collect();
+    for donor in &donors {
+      register(&mut contract, donor.clone());
+      set_caller(donor.clone(), 200, 0);
+      contract.create_subscription(terms.clone());
+    }
+    set_caller(alice(), 100, 0);
+    contract.create_subscription(terms);
+    let used =
+      |contract: &Contract, donor: &AccountId| contract.accounts.get(donor).unwrap().storage_used;
+
+    // The first donor also opens the round tallies, the second pays what the third will
+    set_caller(master(), 0, 0);
+    let before = used(&contract, &donors[0]);
+    assert_eq!(contract.process_due_subscriptions(1), 1);
+    let first = used(&contract, &donors[0]) - before;
+    let before = used(&contract, &donors[1]);
+    assert_eq!(contract.process_due_subscriptions(1), 1);
+    let cost = used(&contract, &donors[1]) - before;
+    assert!(cost > SUBSCRIPTION_DONATION_STORAGE);
+    let subscription = contract.get_subscription(2).unwrap();
+    assert!(first <= contract.subscription_donation_storage(&subscription));
+
+    // A donor one byte short misses the donation without stopping the batch
+    let available = contract.accounts.get(&donors[2]).unwrap().available();
+    set_caller(donors[2].clone(), 1, 0);
+    contract.storage_withdraw(Some(U128(
+      available - Balance::from(cost - 1) * env::storage_byte_cost(),
+    )));
+    set_caller(master(), 0, 0);
+    assert_eq!(contract.process_due_subscriptions(10), 1);
+    let subscription = contract.get_subscription(2).unwrap();
+    assert_eq!(
+      (subscription.donations, subscription.next_due),
+      (0, MIN_SUBSCRIPTION_INTERVAL)
+    );
+    assert_eq!(contract.get_subscription(3).unwrap().donations, 1);
+    assert_eq!(contract.process_due_subscriptions(10), 0);
+  }
+
+  fn milestone_status(contract: &Contract, index: usize) -> MilestoneStatus {
+    contract.get_campaign_funds("wells".to_string()).milestones[index]
+      .status
+      .clone()
+  }
+
+  // Wells with two milestones, 1000 from alice and from dalmasonto, finalised with redcross
+  fn milestone_campaign(voting: Option<MilestoneVoting>) -> Contract {
+    let mut contract = setup();
+    set_caller(supercode(), 0, 0);
+    let milestones = vec![
+      MilestoneTerms {
+        description: "Dig".to_string(),
+        share_bps: 6_000,
+        evidence: "Photos of the well".to_string(),
+      },
+      MilestoneTerms {
+        description: "Pump".to_string(),
+        share_bps: 4_000,
+        evidence: "Water test".to_string(),
+      },
+    ];
+    contract.set_campaign_milestones("wells".to_string(), milestones);
+    contract.set_campaign_milestone_voting("wells".to_string(), voting);
+    donate_to(&mut contract, alice(), campaign("wells"), 1000, 0);
+    donate(&mut contract, "d1", 1000);
+    set_caller(supercode(), 0, 0);
+    contract.add_campaign_partner("wells".to_string(), "redcross".to_string());
+    set_caller(dalmasonto(), 0, 0);
+    contract.campaign_vote("wells".to_string(), "redcross".to_string());
+    set_caller(supercode(), 0, 1_672_531_200_000_000_000);
+    contract.finalize_campaign("wells".to_string());
+    contract
+  }
+
+  #[test]
+  fn test_campaign_milestones() {
+    let mut contract = milestone_campaign(None);
+    assert_eq!(
+      contract
+        .get_campaign_contribution("wells".to_string(), alice())
+        .0,
+      1000
+    );
+
+    // Rejected evidence can be submitted again
+    let hash = Base58CryptoHash::from([7; 32]);
+    set_caller(alice(), 0, 0);
+    contract.submit_milestone_evidence("wells".to_string(), 0, "ipfs://dig".to_string(), hash);
+    assert_eq!(milestone_status(&contract, 0), MilestoneStatus::Submitted);
+    set_caller(master(), 0, 0);
+    contract.reject_milestone("wells".to_string(), 0);
+    assert_eq!(milestone_status(&contract, 0), MilestoneStatus::Pending);
+    set_caller(alice(), 0, 0);
+    contract.submit_milestone_evidence("wells".to_string(), 0, "ipfs://dig2".to_string(), hash);
+    set_caller(master(), 0, 0);
+    contract.approve_milestone("wells".to_string(), 0);
+    let funds = contract.get_campaign_funds("wells".to_string());
+    assert_eq!(
+      (funds.milestones[0].amount.0, funds.released.0),
+      (1200, 1200)
+    );
+
+    set_caller(alice(), 0, 0);
+    contract.withdraw_milestone("wells".to_string(), 0);
+    assert_eq!(milestone_status(&contract, 0), MilestoneStatus::Withdrawn);
+    set_promise_result(PromiseResult::Failed);
+    assert!(!contract.on_milestone_withdrawn("wells".to_string(), 0));
+    assert_eq!(milestone_status(&contract, 0), MilestoneStatus::Approved);
+
+    // Cancelling refunds the 800 that was not released, by what each donor gave
+    set_caller(supercode(), 0, 0);
+    contract.cancel_campaign("wells".to_string());
+    set_caller(dalmasonto(), 0, 0);
+    contract.claim_refund("wells".to_string());
+    let funds = contract.get_campaign_funds("wells".to_string());
+    assert_eq!((funds.raised.0, funds.refunded.0), (2000, 400));
+    assert_eq!(
+      contract
+        .get_campaign_contribution("wells".to_string(), dalmasonto())
+        .0,
+      0
+    );
+    set_promise_result(PromiseResult::Failed);
+    assert!(!contract.on_refund_claimed("wells".to_string(), dalmasonto(), U128(1000), U128(400)));
+    assert_eq!(
+      contract.get_campaign_funds("wells".to_string()).refunded.0,
+      0
+    );
+    set_promise_result(PromiseResult::Successful(Vec::new()));
+    assert!(contract.on_refund_claimed("wells".to_string(), alice(), U128(1000), U128(400)));
+    let logs = event_logs();
+    assert_eq!(logs[0]["event"], "refund_claimed");
+    assert_eq!(logs[0]["data"][0]["amount"], "400");
+  }
+
+  #[test]
+  fn test_cumulative_milestone_release() {
+    let mut contract = milestone_campaign(None);
+    let hash = Base58CryptoHash::from([7; 32]);
+    let approve = |contract: &mut Contract, index: u64| {
+      set_caller(alice(), 0, 0);
+      contract.submit_milestone_evidence("wells".to_string(), index, "ipfs://x".to_string(), hash);
+      set_caller(master(), 0, 0);
+      contract.approve_milestone("wells".to_string(), index);
+    };
+    let add_funds = |contract: &mut Contract, amount: u128| {
+      let mut wells = contract.campaigns.get(&"wells".to_string()).unwrap();
+      wells.current += amount;
+      contract.campaigns.insert(&"wells".to_string(), &wells);
+    };
+    // Campaigns with milestones release through them
+    let funds = contract.get_campaign_funds("wells".to_string());
+    assert_eq!(
+      releasable_amount(&funds, 2000),
+      Err(ContractError::MilestonesOutstanding)
+    );
+    approve(&mut contract, 0);
+    // Funds coming in between approvals, as a round paying out would
+    add_funds(&mut contract, 1000);
+    approve(&mut contract, 1);
+    let funds = contract.get_campaign_funds("wells".to_string());
+    assert_eq!(
+      (funds.milestones[1].amount.0, funds.released.0),
+      (1800, 3000)
+    );
+    assert_eq!(
+      releasable_amount(&funds, 3000),
+      Err(ContractError::NothingToRelease)
+    );
+    // Anything after the last approval is released in one payout
+    add_funds(&mut contract, 500);
+    set_caller(alice(), 0, 0);
+    contract.release_campaign_funds("wells".to_string());
+    assert_eq!(
+      contract.get_campaign_funds("wells".to_string()).released.0,
+      3500
+    );
+  }
+
+  #[test]
+  fn test_release_campaign_funds() {
+    let mut contract = setup();
+    donate(&mut contract, "d1", 1000);
+    set_caller(supercode(), 0, 0);
+    contract.add_campaign_partner("wells".to_string(), "redcross".to_string());
+    set_caller(dalmasonto(), 0, 0);
+    contract.campaign_vote("wells".to_string(), "redcross".to_string());
+    set_caller(supercode(), 0, 1_672_531_200_000_000_000);
+    contract.finalize_campaign("wells".to_string());
+
+    set_caller(alice(), 0, 0);
+    contract.release_campaign_funds("wells".to_string());
+    let funds = contract.get_campaign_funds("wells".to_string());
+    assert_eq!(funds.released.0, 1000);
+    assert_eq!(
+      releasable_amount(&funds, 1000),
+      Err(ContractError::NothingToRelease)
+    );
+    set_promise_result(PromiseResult::Failed);
+    assert!(!contract.on_campaign_funds_released("wells".to_string(), U128(1000)));
+    let funds = contract.get_campaign_funds("wells".to_string());
+    assert_eq!(releasable_amount(&funds, 1000), Ok(1000));
+    set_promise_result(PromiseResult::Successful(Vec::new()));
+    assert!(contract.on_campaign_funds_released("wells".to_string(), U128(1000)));
+    let logs = event_logs();
+    assert_eq!(logs[0]["event"], "payout_sent");
+    assert_eq!(logs[0]["data"][0]["memo"], "campaign:wells");
+  }
+
+  #[test]
+  fn test_campaign_token() {
+    let mut contract = setup();
+    usdn_campaign(&mut contract, "usdn-wells");
+    let id = "usdn-wells".to_string();
+    assert_eq!(
+      contract.campaign_in_token(&id, NEAR_TOKEN).err(),
+      Some(ContractError::TokenMismatch)
+    );
+    let terms = MatchingTerms {
+      target: campaign("usdn-wells"),
+      ratio_bps: ONE_TO_ONE_BPS,
+      cap: None,
+    };
+    assert_eq!(
+      contract.check_matching_pool(NEAR_TOKEN, ONE_NEAR, &terms),
+      Err(ContractError::TokenMismatch)
+    );
+
+    // Only usdn reaches the campaign, so cancelling refunds usdn
+    set_caller(master(), 0, 0);
+    contract.create_donation(DonationInput {
+      id: "d1".to_string(),
+      donor: dalmasonto(),
+      token: usdn().to_string(),
+      amount: U128(100),
+      tip: 0,
+      amount_usd: 1.0,
+      target: campaign("usdn-wells"),
+      anonymous: false,
+      message: None,
+      dedication: None,
+    });
+    assert!(contract.campaign_in_token(&id, usdn().as_str()).is_ok());
+    set_caller(supercode(), 0, 0);
+    contract.cancel_campaign(id.clone());
+    set_caller(dalmasonto(), 0, 0);
+    contract.claim_refund(id.clone());
+    let funds = contract.get_campaign_funds(id.clone());
+    assert_eq!((funds.raised.0, funds.refunded.0), (100, 100));
+    set_promise_result(PromiseResult::Successful(Vec::new()));
+    assert!(contract.on_refund_claimed(id, dalmasonto(), U128(100), U128(100)));
+    let logs = event_logs();
+    assert_eq!(logs[0]["data"][0]["token"], usdn().to_string());
+  }
+
+  #[test]
+  fn test_milestone_votes() {
+    let voting = MilestoneVoting {
+      quorum_bps: 6_000,
+      threshold_bps: 5_000,
+    };
+    let mut contract = milestone_campaign(Some(voting));
+    let period = MIN_MILESTONE_VOTE_PERIOD;
+    let hash = Base58CryptoHash::from([7; 32]);
+    set_caller(alice(), 0, 1000);
+    contract.submit_milestone_evidence("wells".to_string(), 1, "ipfs://pump".to_string(), hash);
+    let vote = contract.get_milestone_vote("wells".to_string(), 1).unwrap();
+    assert_eq!((vote.closes_on, vote.weight.0), (1000 + period, 2000));
+    assert_eq!(vote.quorum_bps, 6_000);
+    // The campaign takes no donations that would change the weights until the vote closes,
+    // cause pool allocations included
+    assert!(contract.milestone_vote_open(&"wells".to_string()));
+    assert_eq!(
+      contract
+        .pool_share_campaign("water", NEAR_TOKEN, &"wells".to_string())
+        .err(),
+      Some(ContractError::MilestoneVoteOpen)
+    );
+
+    // Half of the weight voted, short of the campaign's quorum
+    set_caller(dalmasonto(), 0, 1050);
+    contract.vote_milestone("wells".to_string(), 1, true);
+    set_caller(master(), 0, 1000 + period);
+    assert!(!contract.milestone_vote_open(&"wells".to_string()));
+    assert_eq!(
+      contract.close_milestone_vote("wells".to_string(), 1),
+      MilestoneStatus::Pending
+    );
+
+    // New evidence opens a new vote, everyone can vote again
+    set_caller(alice(), 0, 2000 + period);
+    contract.submit_milestone_evidence("wells".to_string(), 1, "ipfs://pump2".to_string(), hash);
+    set_caller(dalmasonto(), 0, 2000 + period);
+    contract.vote_milestone("wells".to_string(), 1, true);
+    set_caller(alice(), 0, 2000 + period);
+    contract.vote_milestone("wells".to_string(), 1, false);
+    let vote = contract.get_milestone_vote("wells".to_string(), 1).unwrap();
+    assert_eq!(
+      (vote.approve.0, vote.reject.0, vote.voters),
+      (1000, 1000, 2)
+    );
+    assert!(vote.approved());
+    set_caller(master(), 0, 2000 + 2 * period);
+    assert_eq!(
+      contract.close_milestone_vote("wells".to_string(), 1),
+      MilestoneStatus::Approved
+    );
+    let milestone = &contract.get_campaign_funds("wells".to_string()).milestones[1];
+    assert_eq!(
+      (milestone.amount.0, milestone.approved_by.clone()),
+      (800, None)
+    );
+
+    // A guardian settling the evidence ends its vote
+    set_caller(alice(), 0, 3000 + 2 * period);
+    contract.submit_milestone_evidence("wells".to_string(), 0, "ipfs://dig".to_string(), hash);
+    set_caller(master(), 0, 3000 + 2 * period);
+    contract.approve_milestone("wells".to_string(), 0);
+    assert_eq!(
+      contract.find_milestone_vote("wells", 0).err(),
+      Some(ContractError::MilestoneVoteNotFound)
+    );
+    assert!(!contract.milestone_vote_open(&"wells".to_string()));
+    assert_eq!(
+      check_vote_period(100),
+      Err(ContractError::VotePeriodTooShort)
+    );
+    assert_eq!(check_vote_period(period), Ok(()));
   }
 
+  #[test]
+  fn test_flags() {
+    let mut contract = setup();
+    let terms = SubscriptionTerms {
+      target: campaign("wells"),
+      amount: U128(100),
+      amount_usd: 1.0,
+      interval: MIN_SUBSCRIPTION_INTERVAL,
+      anonymous: None,
+    };
+    set_caller(alice(), 500, 0);
+    let subscription = contract.create_subscription(terms);
+
+    let wells = FlagTarget::Campaign {
+      id: "wells".to_string(),
+    };
+    set_caller(dalmasonto(), FLAG_BOND, 0);
+    let id = contract.flag(
+      wells.clone(),
+      "Fake wells".to_string(),
+      "ipfs://report".to_string(),
+    );
+    set_caller(master(), 0, 0);
+    contract.freeze_flagged(id);
+    assert!(contract.is_frozen(wells.clone()));
+    assert_eq!(event_logs()[0]["data"][0]["change"], "frozen");
+
+    // Frozen campaigns miss their subscription donations
+    assert_eq!(contract.process_due_subscriptions(10), 0);
+    assert!(contract.get_subscription(subscription).unwrap().active);
+
+    // Upholding cancels the campaign and lets go of the freeze
+    contract.uphold_flag(id);
+    let flag = contract.get_flag(id).unwrap();
+    assert_eq!(
+      (flag.status, flag.reviewed_by),
+      (FlagStatus::Upheld, Some(master()))
+    );
+    assert!(!contract.is_frozen(wells));
+    assert!(contract.is_campaign_cancelled(&"wells".to_string()));
+
+    // The bond goes back through a payout, one that failed can be claimed again
+    assert_eq!(contract.get_flag(id).unwrap().bond.0, 0);
+    set_promise_result(PromiseResult::Failed);
+    assert!(!contract.on_flag_bond_returned(id, U128(FLAG_BOND)));
+    assert_eq!(contract.get_flag(id).unwrap().bond.0, FLAG_BOND);
+    set_caller(dalmasonto(), 0, 0);
+    contract.claim_flag_bond(id);
+    assert_eq!(contract.get_flag(id).unwrap().bond.0, 0);
+    set_promise_result(PromiseResult::Successful(Vec::new()));
+    assert!(contract.on_flag_bond_returned(id, U128(FLAG_BOND)));
+    assert_eq!(event_logs()[0]["data"][0]["memo"], format!("flag:{}", id));
+    set_caller(master(), 0, 0);
+
+    // Spam flags leave their bond in the treasury
+    let redcross = FlagTarget::Partner {
+      id: "redcross".to_string(),
+    };
+    contract.verify_partner("redcross".to_string(), true);
+    set_caller(dalmasonto(), FLAG_BOND, 0);
+    let spam = contract.flag(redcross.clone(), "Spam".to_string(), String::new());
+    let upheld = contract.flag(redcross, "Not a charity".to_string(), String::new());
+    set_caller(master(), 0, 0);
+    contract.dismiss_flag(spam, true);
+    assert_eq!(contract.get_flag(spam).unwrap().bond.0, 0);
+    assert_eq!(
+      contract.treasury_balance(&NEAR_TOKEN.to_string()),
+      FLAG_BOND
+    );
+    contract.uphold_flag(upheld);
+    assert!(
+      !contract
+        .get_partner("redcross".to_string())
+        .unwrap()
+        .verified
+    );
+    assert_eq!(contract.get_flags(1, 10).count, 3);
+  }
+
+  #[test]
+  fn test_flags_share_freeze() {
+    let mut contract = setup();
+    set_caller(supercode(), 0, 0);
+    contract.create_event(
+      "gala".to_string(),
+      "Gala".to_string(),
+      "2022-06-01".to_string(),
+      "water".to_string(),
+      "Charity gala".to_string(),
+      U128(10 * ONE_NEAR),
+      NEAR_TOKEN.to_string(),
+      "Nairobi".to_string(),
+      "physical".to_string(),
+      "".to_string(),
+      "".to_string(),
+      "2022,6,1".to_string(),
+      "img".to_string(),
+    );
+    let gala = FlagTarget::Event {
+      id: "gala".to_string(),
+    };
+    let first = freeze(&mut contract, gala.clone());
+    set_caller(dalmasonto(), FLAG_BOND, 0);
+    let second = contract.flag(gala.clone(), "Fake".to_string(), String::new());
+    let third = contract.flag(gala.clone(), "Fake".to_string(), String::new());
+
+    // Resolving a flag that didn't freeze the event leaves the freeze alone
+    set_caller(master(), 0, 0);
+    contract.dismiss_flag(second, false);
+    assert_eq!(contract.frozen.get(&gala), Some(first));
+
+    // Upholding takes over the freeze for good, the first review can't lift it any more
+    contract.uphold_flag(third);
+    assert_eq!(contract.frozen.get(&gala), Some(third));
+    contract.dismiss_flag(first, false);
+    assert!(contract.is_frozen(gala));
+  }
 }
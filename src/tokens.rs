@@ -0,0 +1,194 @@
+use near_contract_standards::fungible_token::metadata::FungibleTokenMetadata;
+use near_contract_standards::storage_management::StorageBalance;
+use near_sdk::{ext_contract, Promise, PromiseOrValue, PromiseResult};
+
+use crate::constants::{GAS_FOR_BASIC_OP, GAS_FOR_FT_METADATA, NEAR_TOKEN};
+use crate::errors::{ensure, ContractError, OrPanic};
+use crate::*;
+
+// Calls made on accepted token contracts
+#[ext_contract(ext_ft)]
+pub trait FungibleTokenContract {
+  fn ft_metadata(&self) -> FungibleTokenMetadata;
+  fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>);
+  fn storage_deposit(
+    &mut self,
+    account_id: Option<AccountId>,
+    registration_only: Option<bool>,
+  ) -> StorageBalance;
+  fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance>;
+}
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AcceptedToken {
+  pub id: AccountId,
+  pub metadata: Option<TokenMetadata>, // None until fetched from the token contract
+  pub enabled: bool,                   // Disabled tokens are kept but refused for new donations
+  pub added_by: AccountId,
+  pub added_on: Timestamp,
+}
+
+impl From<(AccountId, FungibleTokenMetadata)> for TokenMetadata {
+  fn from((token, metadata): (AccountId, FungibleTokenMetadata)) -> Self {
+    Self {
+      address: token.to_string(),
+      name: metadata.name,
+      symbol: metadata.symbol,
+      icon: metadata.icon.unwrap_or_default(),
+      decimals: metadata.decimals,
+    }
+  }
+}
+
+#[near_bindgen]
+impl Contract {
+  // Without metadata it is fetched with ft_metadata from the token contract itself
+  pub fn add_token(
+    &mut self,
+    token: AccountId,
+    metadata: Option<TokenMetadata>,
+  ) -> PromiseOrValue<bool> {
+    self.assert_guardian();
+    check_token_metadata(&token, &metadata).or_panic();
+    let fetch = metadata.is_none();
+    self.insert_token(token.clone(), metadata);
+    if fetch {
+      return PromiseOrValue::Promise(self.fetch_token_metadata(token));
+    }
+    PromiseOrValue::Value(true)
+  }
+
+  pub fn refresh_token_metadata(&mut self, token: AccountId) -> Promise {
+    self.assert_guardian();
+    self.find_token(&token).or_panic();
+    self.fetch_token_metadata(token)
+  }
+
+  #[private]
+  pub fn on_ft_metadata(&mut self, token: AccountId) -> bool {
+    let metadata = match env::promise_result(0) {
+      PromiseResult::Successful(result) => {
+        near_sdk::serde_json::from_slice::<FungibleTokenMetadata>(&result).ok()
+      }
+      _ => None,
+    };
+    // The token may have been removed while the call was in flight
+    match (metadata, self.tokens.get(&token)) {
+      (Some(metadata), Some(mut accepted)) => {
+        accepted.metadata = Some(TokenMetadata::from((token.clone(), metadata)));
+        self.tokens.insert(&token, &accepted);
+        true
+      }
+      _ => false,
+    }
+  }
+
+  pub fn remove_token(&mut self, token: AccountId) {
+    self.assert_guardian();
+    self.find_token(&token).or_panic();
+    self.tokens.remove(&token);
+    self.tokens_count -= 1;
+  }
+
+  pub fn set_token_enabled(&mut self, token: AccountId, enabled: bool) {
+    self.assert_guardian();
+    let mut accepted = self.find_token(&token).or_panic();
+    accepted.enabled = enabled;
+    self.tokens.insert(&token, &accepted);
+  }
+
+  pub fn get_tokens(&self) -> Vec<AcceptedToken> {
+    self.tokens.values().collect()
+  }
+
+  pub fn get_token(&self, token: AccountId) -> Option<AcceptedToken> {
+    self.tokens.get(&token)
+  }
+}
+
+impl Contract {
+  pub fn insert_token(&mut self, token: AccountId, metadata: Option<TokenMetadata>) {
+    let enabled = match self.tokens.get(&token) {
+      Some(existing) => existing.enabled,
+      None => {
+        self.tokens_count += 1;
+        true
+      }
+    };
+    let accepted = AcceptedToken {
+      id: token.clone(),
+      metadata,
+      enabled,
+      added_by: env::predecessor_account_id(),
+      added_on: env::block_timestamp(),
+    };
+    self.tokens.insert(&token, &accepted);
+  }
+
+  fn fetch_token_metadata(&self, token: AccountId) -> Promise {
+    ext_ft::ext(token.clone())
+      .with_static_gas(GAS_FOR_FT_METADATA)
+      .ft_metadata()
+      .then(
+        Self::ext(env::current_account_id())
+          .with_static_gas(GAS_FOR_BASIC_OP)
+          .on_ft_metadata(token),
+      )
+  }
+
+  pub fn find_token(&self, token: &AccountId) -> Result<AcceptedToken, ContractError> {
+    self.tokens.get(token).ok_or(ContractError::TokenNotFound)
+  }
+
+  // Fungible tokens are only accepted while whitelisted and enabled
+  pub fn is_token_accepted(&self, token: &AccountId) -> bool {
+    self
+      .tokens
+      .get(token)
+      .is_some_and(|accepted| accepted.enabled)
+  }
+
+  pub fn assert_token_accepted(&self, token: &AccountId) {
+    ensure(
+      self.is_token_accepted(token),
+      ContractError::TokenNotAccepted,
+    );
+  }
+
+  // Decimals of a donation token, 0 for tokens whose metadata is not known yet
+  pub fn token_decimals(&self, token: &str) -> u8 {
+    if token == NEAR_TOKEN {
+      return 24;
+    }
+    self
+      .token_metadata(token)
+      .map_or(0, |metadata| metadata.decimals)
+  }
+
+  // Metadata shown next to donations, donation tokens are stored as plain strings
+  pub fn token_metadata(&self, token: &str) -> Option<TokenMetadata> {
+    AccountId::from_str(token)
+      .ok()
+      .and_then(|token| self.tokens.get(&token))
+      .and_then(|accepted| accepted.metadata)
+  }
+}
+
+// Token addresses given as strings, ie in TokenMetadata, must be the token contract's account id
+pub fn token_account(address: &str) -> Result<AccountId, ContractError> {
+  AccountId::from_str(address).map_err(|_| ContractError::InvalidTokenAddress)
+}
+
+// Metadata given with a token has to be about that token, views show it next to its donations
+pub fn check_token_metadata(
+  token: &AccountId,
+  metadata: &Option<TokenMetadata>,
+) -> Result<(), ContractError> {
+  match metadata {
+    Some(metadata) if metadata.address != token.as_str() => {
+      Err(ContractError::TokenAddressMismatch)
+    }
+    _ => Ok(()),
+  }
+}
@@ -0,0 +1,264 @@
+use crate::causepools::PoolBalance;
+use crate::errors::{ensure, ContractError, OrPanic};
+use crate::flags::FlagTarget;
+use crate::guardians::check_quorum;
+use crate::logs::{log_payout_sent, PayoutSentLog};
+use crate::*;
+use near_sdk::PromiseOrValue;
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum GrantRecipient {
+  Partner { id: String },
+  Campaign { id: String },
+}
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "lowercase")]
+pub enum GrantStatus {
+  Proposed,  // Waiting for guardian approvals
+  Approved,  // Quorum reached, ready to execute
+  Executing, // Transfer sent, waiting for the result
+  Executed,
+  Rejected,
+}
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct GrantProposal {
+  pub id: u64,
+  pub proposer: AccountId,
+  pub recipient: GrantRecipient,
+  pub token: String,
+  pub amount: U128,
+  pub description: String,
+  pub approvals: Vec<AccountId>, // Guardians who approved the grant
+  pub status: GrantStatus,
+  pub created_on: Timestamp,
+  pub executed_on: Option<Timestamp>,
+}
+
+#[near_bindgen]
+impl Contract {
+  // Guardians propose spending general donations on a verified partner or a campaign
+  pub fn propose_grant(
+    &mut self,
+    recipient: GrantRecipient,
+    token: String,
+    amount: U128,
+    description: String,
+  ) -> u64 {
+    self.assert_guardian();
+    self.grant_recipient_ok(&recipient, &token).or_panic();
+    ensure(
+      self.treasury_balance(&token) >= amount.0,
+      ContractError::InsufficientTreasury,
+    );
+
+    let grant = GrantProposal {
+      id: self.grants_count,
+      proposer: env::predecessor_account_id(),
+      recipient,
+      token,
+      amount,
+      description,
+      approvals: Vec::new(),
+      status: GrantStatus::Proposed,
+      created_on: env::block_timestamp(),
+      executed_on: None,
+    };
+    self.grants.insert(&grant.id, &grant);
+    self.grants_count += 1;
+    grant.id
+  }
+
+  pub fn approve_grant(&mut self, id: u64) {
+    self.assert_guardian();
+    let mut grant = self.find_grant(id).or_panic();
+    ensure(
+      grant.status == GrantStatus::Proposed,
+      ContractError::GrantNotPending,
+    );
+    let guardian = env::predecessor_account_id();
+    ensure(
+      !grant.approvals.contains(&guardian),
+      ContractError::AlreadyApproved,
+    );
+
+    grant.approvals.push(guardian);
+    if grant.approvals.len() as u64 >= self.config.grant_quorum {
+      grant.status = GrantStatus::Approved;
+    }
+    self.grants.insert(&id, &grant);
+  }
+
+  pub fn reject_grant(&mut self, id: u64) {
+    self.assert_guardian();
+    let mut grant = self.find_grant(id).or_panic();
+    ensure(
+      grant.status == GrantStatus::Proposed || grant.status == GrantStatus::Approved,
+      ContractError::GrantNotPending,
+    );
+    grant.status = GrantStatus::Rejected;
+    self.grants.insert(&id, &grant);
+  }
+
+  // Anyone can execute a grant once the guardians reached quorum
+  pub fn execute_grant(&mut self, id: u64) -> PromiseOrValue<bool> {
+    let mut grant = self.find_grant(id).or_panic();
+    ensure(
+      grant.status == GrantStatus::Approved,
+      ContractError::GrantNotApproved,
+    );
+    self
+      .grant_recipient_ok(&grant.recipient, &grant.token)
+      .or_panic();
+    let amount_usd = self.debit_treasury(&grant.token, grant.amount.0);
+
+    match grant.recipient.clone() {
+      GrantRecipient::Campaign { id: campaign_id } => {
+        let mut campaign = self.get_campaign(campaign_id.clone()).unwrap();
+        campaign.current += grant.amount.0;
+        campaign.current_usd += amount_usd;
+        self.campaigns.insert(&campaign_id, &campaign);
+        self.log_campaign_change(campaign_id, "grant");
+
+        grant.status = GrantStatus::Executed;
+        grant.executed_on = Some(env::block_timestamp());
+        self.grants.insert(&id, &grant);
+        PromiseOrValue::Value(true)
+      }
+      GrantRecipient::Partner { id: partner_id } => {
+        let partner = self.get_partner(partner_id).unwrap();
+        grant.status = GrantStatus::Executing;
+        self.grants.insert(&id, &grant);
+
+        PromiseOrValue::Promise(
+          self
+            .transfer_funds(&grant.token, partner.created_by, grant.amount.0)
+            .then(Self::ext(env::current_account_id()).on_grant_transfer(id, amount_usd)),
+        )
+      }
+    }
+  }
+
+  #[private]
+  pub fn on_grant_transfer(&mut self, id: u64, amount_usd: f64) -> bool {
+    let mut grant = self.find_grant(id).or_panic();
+    if !Self::payout_succeeded() {
+      // Put the funds back and let the grant be executed again
+      self.credit_treasury(&grant.token, grant.amount.0, amount_usd);
+      grant.status = GrantStatus::Approved;
+      self.grants.insert(&id, &grant);
+      return false;
+    }
+
+    grant.status = GrantStatus::Executed;
+    grant.executed_on = Some(env::block_timestamp());
+    self.grants.insert(&id, &grant);
+    if let GrantRecipient::Partner { id: partner_id } = grant.recipient {
+      log_payout_sent(PayoutSentLog {
+        token: grant.token,
+        receiver_id: self.find_partner(&partner_id).or_panic().created_by,
+        amount: grant.amount,
+        memo: format!("grant:{}", id),
+      });
+    }
+    true
+  }
+
+  pub fn set_grant_quorum(&mut self, quorum: u64) {
+    self.assert_owner();
+    check_quorum(quorum).or_panic();
+    self.config.grant_quorum = quorum;
+  }
+
+  pub fn get_treasury(&self) -> Vec<(String, PoolBalance)> {
+    self.treasury.to_vec()
+  }
+
+  pub fn get_grant(&self, id: u64) -> Option<GrantProposal> {
+    self.grants.get(&id)
+  }
+
+  pub fn get_grants(&self, page: usize, limit: usize) -> Response<GrantProposal> {
+    let start_index = (page - 1) * limit;
+
+    let grants: Vec<GrantProposal> = self.grants.values().skip(start_index).take(limit).collect();
+
+    Response {
+      results: grants,
+      count: self.grants.len(),
+    }
+  }
+}
+
+impl Contract {
+  pub fn treasury_balance(&self, token: &String) -> u128 {
+    self
+      .treasury
+      .get(token)
+      .map_or(0, |balance| balance.amount.0)
+  }
+
+  pub fn credit_treasury(&mut self, token: &String, amount: u128, amount_usd: f64) {
+    let mut balance = self.treasury.get(token).unwrap_or(PoolBalance {
+      amount: U128(0),
+      amount_usd: 0.0,
+    });
+    balance.amount = U128(balance.amount.0 + amount);
+    balance.amount_usd += amount_usd;
+    self.treasury.insert(token, &balance);
+  }
+
+  // Returns the USD value of the debited share of the treasury
+  pub fn debit_treasury(&mut self, token: &String, amount: u128) -> f64 {
+    let mut balance = self
+      .treasury
+      .get(token)
+      .ok_or(ContractError::InsufficientTreasury)
+      .or_panic();
+    ensure(
+      balance.amount.0 >= amount,
+      ContractError::InsufficientTreasury,
+    );
+    let amount_usd = balance.take(amount).or_panic();
+    self.treasury.insert(token, &balance);
+    amount_usd
+  }
+
+  pub fn find_grant(&self, id: u64) -> Result<GrantProposal, ContractError> {
+    self.grants.get(&id).ok_or(ContractError::GrantNotFound)
+  }
+
+  // Partners have to be verified, campaigns open and taking the grant's token, neither frozen
+  pub fn grant_recipient_ok(
+    &self,
+    recipient: &GrantRecipient,
+    token: &str,
+  ) -> Result<(), ContractError> {
+    let frozen = match recipient {
+      GrantRecipient::Partner { id } => {
+        if !self.find_partner(id)?.verified {
+          return Err(ContractError::PartnerNotVerified);
+        }
+        FlagTarget::Partner { id: id.clone() }
+      }
+      GrantRecipient::Campaign { id } => {
+        if self.find_campaign(id)?.token != token {
+          return Err(ContractError::TokenMismatch);
+        }
+        if self.is_campaign_cancelled(id) {
+          return Err(ContractError::CampaignCancelled);
+        }
+        FlagTarget::Campaign { id: id.clone() }
+      }
+    };
+    if self.is_frozen(frozen) {
+      return Err(ContractError::Frozen);
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,107 @@
+use near_sdk::json_types::Base58CryptoHash;
+use near_sdk::{CryptoHash, Gas, Promise};
+
+use crate::constants::GAS_FOR_UPGRADE;
+use crate::errors::{ensure, ContractError, OrPanic};
+use crate::guardians::check_quorum;
+use crate::*;
+
+// New contract code waiting for guardian approval, the code itself is kept in staged_code
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StagedUpgrade {
+  pub hash: Base58CryptoHash, // sha256 of the staged wasm
+  pub size: u64,              // Bytes of the staged wasm
+  pub staged_by: AccountId,
+  pub staged_on: Timestamp,
+  pub approvals: Vec<AccountId>, // Guardians who approved this code
+}
+
+#[near_bindgen]
+impl Contract {
+  // The wasm is passed as the raw call input, not JSON. Staging again replaces the code and its approvals.
+  pub fn stage_upgrade(&mut self) {
+    self.assert_guardian();
+    let code = env::input().unwrap_or_default();
+    ensure(!code.is_empty(), ContractError::NoStagedCode);
+
+    let hash: CryptoHash = env::sha256(&code).try_into().unwrap();
+    self.staged_code.set(&code);
+    self.staged_upgrade = Some(StagedUpgrade {
+      hash: Base58CryptoHash::from(hash),
+      size: code.len() as u64,
+      staged_by: env::predecessor_account_id(),
+      staged_on: env::block_timestamp(),
+      approvals: Vec::new(),
+    });
+  }
+
+  // The hash makes sure guardians approve the code they reviewed, not whatever was staged last
+  pub fn approve_upgrade(&mut self, hash: Base58CryptoHash) {
+    self.assert_guardian();
+    let mut staged = self.find_staged_upgrade().or_panic();
+    ensure(staged.hash == hash, ContractError::CodeHashMismatch);
+    let guardian = env::predecessor_account_id();
+    ensure(
+      !staged.approvals.contains(&guardian),
+      ContractError::AlreadyApproved,
+    );
+
+    staged.approvals.push(guardian);
+    self.staged_upgrade = Some(staged);
+  }
+
+  pub fn cancel_upgrade(&mut self) {
+    self.assert_guardian();
+    self.staged_code.remove();
+    self.staged_upgrade = None;
+  }
+
+  // Deploys the approved code and migrates the state in the same batch, a failed migrate reverts the deploy
+  pub fn upgrade(&mut self) -> Promise {
+    self.assert_guardian();
+    let staged = self.find_staged_upgrade().or_panic();
+    ensure(
+      staged.approvals.len() as u64 >= self.config.upgrade_quorum,
+      ContractError::UpgradeNotApproved,
+    );
+    let code = self
+      .staged_code
+      .get()
+      .ok_or(ContractError::NoStagedCode)
+      .or_panic();
+    let gas = migrate_gas(env::prepaid_gas(), env::used_gas()).or_panic();
+    // Cleared before the batch runs, if it fails the code has to be staged and approved again
+    self.staged_code.remove();
+    self.staged_upgrade = None;
+
+    Promise::new(env::current_account_id())
+      .deploy_contract(code)
+      .function_call("migrate".to_string(), b"{}".to_vec(), 0, gas)
+  }
+
+  pub fn set_upgrade_quorum(&mut self, quorum: u64) {
+    self.assert_owner();
+    check_quorum(quorum).or_panic();
+    self.config.upgrade_quorum = quorum;
+  }
+
+  pub fn get_staged_upgrade(&self) -> Option<StagedUpgrade> {
+    self.staged_upgrade.clone()
+  }
+}
+
+impl Contract {
+  fn find_staged_upgrade(&self) -> Result<StagedUpgrade, ContractError> {
+    self.get_staged_upgrade().ok_or(ContractError::NoStagedCode)
+  }
+}
+
+// Gas left for migrate once upgrade keeps its own share
+pub fn migrate_gas(prepaid: Gas, used: Gas) -> Result<Gas, ContractError> {
+  prepaid
+    .0
+    .checked_sub(used.0 + GAS_FOR_UPGRADE.0)
+    .map(Gas)
+    .ok_or(ContractError::UpgradeGas)
+}
@@ -0,0 +1,24 @@
+use crate::events::Date;
+use near_sdk::Timestamp;
+
+const NANOSECONDS_PER_DAY: u64 = 1_000_000_000 * 60 * 60 * 24;
+
+// Calendar date (UTC) of a block timestamp in nanoseconds
+pub fn timestamp_to_date(timestamp: Timestamp) -> Date {
+  // Days since 1970-01-01 to civil date, see http://howardhinnant.github.io/date_algorithms.html
+  let z = (timestamp / NANOSECONDS_PER_DAY) as i64 + 719_468;
+  let era = z / 146_097;
+  let doe = z - era * 146_097;
+  let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let day = doy - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+  Date {
+    year: year as i32,
+    month: month as i32,
+    day: day as i32,
+  }
+}